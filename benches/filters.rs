@@ -0,0 +1,57 @@
+// compares recalculating the feedback filter's coefficients every sample, like the plugin used
+// to, with the cached control rate updates. run with `cargo bench --bench filters`.
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use maeror_delay::filter::{BiquadFilter, FeedbackFilter, FilterEngine, FilterType};
+
+const SAMPLE_RATE: f32 = 48000.0;
+const SAMPLES: usize = 48000;
+
+// a slow sweep, like a smoothed parameter or the filter lfo
+fn cutoff(n: usize) -> f32 {
+    1000.0 + 500.0 * (n as f32 / 20000.0).sin()
+}
+
+// white noise, a constant input would leave the states decaying into denormals
+fn input(n: usize) -> f32 {
+    ((n as u32).wrapping_mul(1_103_515_245).wrapping_add(12345) >> 8) as f32 / 8_388_608.0 - 1.0
+}
+
+fn coefficient_updates(c: &mut Criterion) {
+    let mut group = c.benchmark_group("coefficient updates");
+    group.throughput(Throughput::Elements(SAMPLES as u64));
+
+    group.bench_function("per sample biquad", |b| {
+        let mut filter = BiquadFilter::new();
+        filter.set_sample_rate(SAMPLE_RATE);
+        b.iter(|| {
+            let mut sum = 0.0;
+            for n in 0..SAMPLES {
+                filter.coefficients(FilterType::LowPass2, black_box(cutoff(n)), 0.707, 0.0);
+                sum += filter.process_left(input(n));
+            }
+            sum
+        });
+    });
+
+    for (name, engine) in [("cached biquad", FilterEngine::Biquad), ("cached svf", FilterEngine::Svf)] {
+        group.bench_function(name, |b| {
+            let mut filter = FeedbackFilter::new();
+            filter.set_sample_rate(SAMPLE_RATE);
+            b.iter(|| {
+                let mut sum = 0.0;
+                for n in 0..SAMPLES {
+                    filter.set(engine, FilterType::LowPass2, black_box(cutoff(n)), 0.707, 0.0);
+                    sum += filter.process_left(input(n));
+                }
+                sum
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, coefficient_updates);
+criterion_main!(benches);
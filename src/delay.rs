@@ -1,16 +1,166 @@
-use nih_plug::prelude::Enum;
+use std::f32::consts::PI;
 
-use crate::delay_enums::{DelayTime, DelayTiming};
+use crate::delay_enums::{DelayTime, DelayTiming, DiffusionPosition, Interpolation, ReverseFeedback, TimeChange};
+use crate::diffusion::Diffuser;
+use crate::dynamics::Limiter;
+use crate::filter::{CutFilter, FeedbackFilter, FilterEngine, FilterSlope, FilterType};
+use crate::pitch_shift::PitchShifter;
+use crate::saturation::{Oversampling, SaturationCurve, Saturator};
+use crate::taps::MAX_TAPS;
+
+// longest delay that can be dialed in when the delay runs in free (milliseconds) mode
+pub const MAX_DELAY_MS: f32 = 5000.0;
+// the buffers are allocated once for the longest synced delay at this tempo. slower tempos still
+// work, but their delay times get clamped to the buffer length.
+pub const MIN_BPM: f32 = 40.0;
+// upper limit for the synced part of the buffer, so long bar values at slow tempos don't allocate
+// over a minute of audio per channel. longer delays get clamped to it, same as below MIN_BPM.
+const MAX_SYNCED_DELAY_MS: f32 = 16000.0;
+
+// the interpolators look up to two samples around the read position, so the delay can never get
+// closer than this to the write head or to the end of the buffer
+const MIN_DELAY_SAMPLES: f32 = 2.0;
+const GUARD_SAMPLES: usize = 4;
+// the repeats count as silent once they have decayed this far
+const TAIL_SILENCE_DB: f32 = -90.0;
+// number of log-spaced frequencies the feedback filter's peak gain is searched at
+const PEAK_SEARCH_STEPS: usize = 24;
+// how long freezing and unfreezing take to blend between the normal and the frozen loop
+const FREEZE_RAMP_MS: f32 = 30.0;
+
+// one of the two backwards read heads of the reverse mode
+#[derive(Clone, Copy)]
+struct ReverseHead {
+    // samples since this head started its current chunk
+    age: f32,
+    allpass_state: f32,
+}
+
+impl ReverseHead {
+    fn new(age: f32) -> Self {
+        ReverseHead {
+            age,
+            allpass_state: 0.0,
+        }
+    }
+}
+
+// a forward read head that follows its target delay according to the time change behaviour
+#[derive(Clone, Copy)]
+struct ReadHead {
+    // the delay the head is currently at, and the one it should end up at
+    delay_samples: f32,
+    target_delay_samples: f32,
+    // second position used while crossfading between two delay times
+    crossfading: bool,
+    crossfade_delay_samples: f32,
+    crossfade_pos: f32,
+    // previous outputs of the allpass interpolator for both positions
+    allpass_state: f32,
+    crossfade_allpass_state: f32,
+}
+
+impl ReadHead {
+    fn new() -> Self {
+        ReadHead {
+            delay_samples: MIN_DELAY_SAMPLES,
+            target_delay_samples: MIN_DELAY_SAMPLES,
+            crossfading: false,
+            crossfade_delay_samples: MIN_DELAY_SAMPLES,
+            crossfade_pos: 0.0,
+            allpass_state: 0.0,
+            crossfade_allpass_state: 0.0,
+        }
+    }
+
+    // nothing is left to fade between, so the head can go straight to its target
+    fn reset(&mut self) {
+        self.allpass_state = 0.0;
+        self.crossfade_allpass_state = 0.0;
+        self.crossfading = false;
+        self.delay_samples = self.target_delay_samples;
+    }
+}
+
+// an extra read head for the multi-tap mode, with its own level and filter
+#[derive(Clone, Copy)]
+struct Tap {
+    head: ReadHead,
+    gain: f32,
+    // the pan law for this channel, only applied to the output and not to the feedback
+    pan_gain: f32,
+    feedback_source: bool,
+    filter: FeedbackFilter,
+}
+
+impl Tap {
+    fn new() -> Self {
+        Tap {
+            head: ReadHead::new(),
+            gain: 1.0,
+            pan_gain: 1.0,
+            feedback_source: false,
+            filter: FeedbackFilter::new(),
+        }
+    }
+}
 
 pub struct Delay {
     buffer: Vec<f32>,
     max_delay_samples: usize,
     current_sample: usize,
-    delay_samples: usize,
-    delay_time: DelayTime,
-    delay_timing: DelayTiming,
+    // how many of the newest samples were written since the last reset. everything older is
+    // stale and reads as silence, so a reset doesn't have to clear the whole buffer at once.
+    written_samples: usize,
+    // the main read head, replaced by the taps in multi-tap mode
+    head: ReadHead,
+    time_change: TimeChange,
+    // per channel adjustment applied on top of the shared delay time
+    time_scale: f32,
+    time_offset_samples: f32,
+    // wow/flutter, added to every read position
+    modulation_samples: f32,
+    // length of a crossfade or glide in samples
+    transition_samples: f32,
+    // one pole coefficient of the tape glide, follows `transition_samples`
+    glide_coeff: f32,
+    interpolation: Interpolation,
+    // the feedback path: the delayed signal gets scaled, filtered and written back into the buffer
+    feedback: f32,
+    filter: FeedbackFilter,
+    filter_cutoff: f32,
+    // tone shaping in series with the main filter
+    low_cut: CutFilter,
+    high_cut: CutFilter,
+    // shimmer: transposes the feedback signal, so every repeat ends up higher than the last
+    pitch_shifter: PitchShifter,
+    saturator: Saturator,
+    diffuser: Diffuser,
+    diffusion_position: DiffusionPosition,
+    // keeps feedback above 100% from growing past the ceiling
+    limiter: Limiter,
+    // reverse mode: two heads half a chunk apart whose windows add up to one. both share the
+    // phase, so the windows stay complementary even while the chunk length changes.
+    reverse: bool,
+    reverse_feedback: ReverseFeedback,
+    reverse_heads: [ReverseHead; 2],
+    // position of the first head within its chunk, 0..1, the second one is half a chunk ahead
+    reverse_phase: f32,
+    // only changes when one of the heads starts a new chunk, where its window is silent
+    reverse_chunk_samples: f32,
+    // when there are any active taps they replace the main read head
+    taps: [Tap; MAX_TAPS],
+    num_taps: usize,
+    // when frozen the input is ignored and the buffer keeps looping its current contents
+    frozen: bool,
+    // 0 is the normal loop and 1 is fully frozen, ramps towards `frozen` by `freeze_step` per sample
+    freeze_amount: f32,
+    freeze_step: f32,
+    // the frozen loop repeats the buffer at a whole number of samples, latched when freezing, so
+    // it's neither interpolated nor modulated and never loses anything
+    freeze_delay_samples: usize,
+    frozen_sample: f32,
     sample_rate: f32,
-    bpm: f32,
 }
 
 impl Delay {
@@ -19,60 +169,630 @@ impl Delay {
             buffer: vec![0.0; 10],
             max_delay_samples: 10,
             current_sample: 0,
-            delay_samples: 0,
-            delay_time: DelayTime::_1_4,
-            delay_timing: DelayTiming::Straight,
+            written_samples: 0,
+            head: ReadHead::new(),
+            time_change: TimeChange::Jump,
+            time_scale: 1.0,
+            time_offset_samples: 0.0,
+            modulation_samples: 0.0,
+            transition_samples: 1.0,
+            glide_coeff: 1.0 - (-5.0_f32).exp(),
+            interpolation: Interpolation::Linear,
+            feedback: 0.0,
+            filter: FeedbackFilter::new(),
+            filter_cutoff: 20000.0,
+            low_cut: CutFilter::new(true),
+            high_cut: CutFilter::new(false),
+            pitch_shifter: PitchShifter::new(),
+            saturator: Saturator::new(),
+            diffuser: Diffuser::new(),
+            diffusion_position: DiffusionPosition::Feedback,
+            limiter: Limiter::new(),
+            reverse: false,
+            reverse_feedback: ReverseFeedback::Reversed,
+            reverse_heads: [ReverseHead::new(0.0), ReverseHead::new(0.5 * MIN_DELAY_SAMPLES)],
+            reverse_phase: 0.0,
+            reverse_chunk_samples: MIN_DELAY_SAMPLES,
+            taps: [Tap::new(); MAX_TAPS],
+            num_taps: 0,
+            frozen: false,
+            freeze_amount: 0.0,
+            freeze_step: 1.0,
+            freeze_delay_samples: MIN_DELAY_SAMPLES as usize,
+            frozen_sample: 0.0,
             sample_rate: 44100.0,
-            bpm: 120.0,
         }
     }
 
+    // reads the delayed sample and feeds it back into the buffer together with the input
     pub fn process(&mut self, x: f32) -> f32 {
-        let read_index: usize;
-        if (self.current_sample as i32 - self.delay_samples as i32) < 0 {
-            read_index = self.max_delay_samples - (self.delay_samples - self.current_sample);
+        let (delayed_sample, feedback_signal) = self.read();
+        self.write(x, feedback_signal);
+        delayed_sample
+    }
+
+    // writes the input plus the pitch shifted, scaled, saturated, filtered, diffused and limited
+    // feedback signal into the buffer. this is split from `read` so the feedback signal can come from
+    // another channel's delay.
+    pub fn write(&mut self, x: f32, feedback_signal: f32) {
+        let target = if self.frozen { 1.0 } else { 0.0 };
+        self.freeze_amount = if self.freeze_amount < target {
+            (self.freeze_amount + self.freeze_step).min(target)
         } else {
-            read_index = self.current_sample - self.delay_samples;
-        }
-        let delayed_sample = self.buffer[read_index];
+            (self.freeze_amount - self.freeze_step).max(target)
+        };
 
-        // Store the input sample in the buffer
-        self.buffer[self.current_sample] = x;
+        let y = if self.freeze_amount >= 1.0 {
+            // unity feedback straight from the buffer, so nothing decays
+            self.frozen_sample
+        } else {
+            let shifted = self.pitch_shifter.process(feedback_signal);
+            let saturated = self.saturator.process(x + self.feedback * shifted);
+            let filtered = self.filter.process_left(saturated);
+            let filtered = self.high_cut.process_left(self.low_cut.process_left(filtered));
+            let diffused = match self.diffusion_position {
+                DiffusionPosition::Feedback => self.diffuser.process(filtered),
+                DiffusionPosition::Wet => filtered,
+            };
+            let y = self.limiter.process(diffused);
+            // while ramping, the frozen loop gets blended with the normal one
+            self.freeze_amount * self.frozen_sample + (1.0 - self.freeze_amount) * y
+        };
 
+        // Store the sample in the buffer
+        self.buffer[self.current_sample] = y;
 
         // Update the current sample index in a circular manner
         self.current_sample = (self.current_sample + 1) % self.max_delay_samples;
+        self.written_samples = (self.written_samples + 1).min(self.max_delay_samples);
+    }
+
+    // returns the delayed output and the signal that should be fed back. those are the same for a
+    // single read head, but in multi-tap mode only some of the taps feed back. has to be followed
+    // by exactly one `write`.
+    pub fn read(&mut self) -> (f32, f32) {
+        // the frozen loop ignores the taps, the reverse heads and the modulation, those only
+        // change what's heard
+        self.frozen_sample = self.sample_at(self.freeze_delay_samples);
+        let (output, feedback_signal) = self.read_heads();
+        match self.diffusion_position {
+            DiffusionPosition::Wet => (self.diffuser.process(output), feedback_signal),
+            DiffusionPosition::Feedback => (output, feedback_signal),
+        }
+    }
+
+    // the main head, the reverse heads or the taps, whichever are active
+    fn read_heads(&mut self) -> (f32, f32) {
+        if self.num_taps == 0 {
+            if self.reverse {
+                let reversed = self.read_reverse();
+                return match self.reverse_feedback {
+                    ReverseFeedback::Reversed => (reversed, reversed),
+                    ReverseFeedback::Forward => (reversed, self.read_main_head()),
+                };
+            }
+            let delayed_sample = self.read_main_head();
+            return (delayed_sample, delayed_sample);
+        }
+
+        let mut output = 0.0;
+        let mut feedback_signal = 0.0;
+        for index in 0..self.num_taps {
+            let mut head = self.taps[index].head;
+            let delayed_sample = self.read_head(&mut head);
+
+            let tap = &mut self.taps[index];
+            tap.head = head;
+            let y = tap.filter.process_left(delayed_sample) * tap.gain;
+            output += y * tap.pan_gain;
+            if tap.feedback_source {
+                feedback_signal += y;
+            }
+        }
+
+        (output, feedback_signal)
+    }
 
+    fn read_main_head(&mut self) -> f32 {
+        let mut head = self.head;
+        let delayed_sample = self.read_head(&mut head);
+        self.head = head;
         delayed_sample
     }
 
-    pub fn resize_buffers(&mut self, sample_rate: f32, bpm: f32) {
-        // the longest possible delay
-        let new_len = ((DelayTime::get_max_in_beats() * 1.5 * 60.0 * sample_rate) / bpm as f32) as usize;
-        self.max_delay_samples = new_len;
-        self.buffer.resize(new_len, 0.0)
+    // moves a read head towards its target delay according to the time change behaviour and reads
+    // the delayed sample
+    fn read_head(&self, head: &mut ReadHead) -> f32 {
+        match self.time_change {
+            TimeChange::Jump => {
+                head.delay_samples = head.target_delay_samples;
+                head.crossfading = false;
+            },
+            TimeChange::Tape => {
+                // the changing distance between read and write heads is what bends the pitch
+                head.delay_samples += (head.target_delay_samples - head.delay_samples) * self.glide_coeff;
+                head.crossfading = false;
+            },
+            TimeChange::Crossfade => {
+                if !head.crossfading && head.target_delay_samples != head.delay_samples {
+                    head.crossfading = true;
+                    head.crossfade_delay_samples = head.target_delay_samples;
+                    head.crossfade_pos = 0.0;
+                    head.crossfade_allpass_state = 0.0;
+                }
+            },
+        }
+
+        let mut delayed_sample = self.read_at(head.delay_samples + self.modulation_samples, &mut head.allpass_state);
+
+        if head.crossfading {
+            let next_sample = self.read_at(head.crossfade_delay_samples + self.modulation_samples, &mut head.crossfade_allpass_state);
+
+            delayed_sample = delayed_sample * (1.0 - head.crossfade_pos) + next_sample * head.crossfade_pos;
+
+            head.crossfade_pos += 1.0 / self.transition_samples;
+            if head.crossfade_pos >= 1.0 {
+                // the second position becomes the main one, a newer target gets its own crossfade
+                head.crossfading = false;
+                head.delay_samples = head.crossfade_delay_samples;
+                head.allpass_state = head.crossfade_allpass_state;
+            }
+        }
+
+        delayed_sample
+    }
+
+    // plays the buffer backwards in chunks of the delay time. while the write head moves forward
+    // by one sample, a head moves back by one, so its distance grows by two per sample and every
+    // chunk plays the last `chunk_samples` samples in reverse. the windows are sin² and cos² of
+    // the shared phase, so they always add up to one while crossfading between the chunks.
+    fn read_reverse(&mut self) -> f32 {
+        let mut y = 0.0;
+        for index in 0..self.reverse_heads.len() {
+            let mut head = self.reverse_heads[index];
+            let phase = (self.reverse_phase + 0.5 * index as f32).fract();
+            let window = (PI * phase).sin().powi(2);
+            y += window * self.read_at(2.0 * head.age + self.modulation_samples, &mut head.allpass_state);
+            head.age += 1.0;
+            self.reverse_heads[index] = head;
+        }
+
+        // the first head starts a new chunk when the phase wraps, the second one half way through
+        let previous_phase = self.reverse_phase;
+        self.reverse_phase = (self.reverse_phase + 1.0 / self.reverse_chunk_samples).fract();
+        let started = [
+            self.reverse_phase < previous_phase,
+            previous_phase < 0.5 && self.reverse_phase >= 0.5,
+        ];
+        for (index, started) in started.into_iter().enumerate() {
+            if started {
+                let phase = (self.reverse_phase + 0.5 * index as f32).fract();
+                self.reverse_heads[index].age = phase * self.reverse_chunk_samples;
+                // the heads reach back twice the chunk length, which has to fit into the buffer
+                self.reverse_chunk_samples = self.head.target_delay_samples.min(self.max_delay_len() / 2.0);
+            }
+        }
+        y
+    }
+
+    // returns the sample that was written `distance` samples ago
+    fn sample_at(&self, distance: usize) -> f32 {
+        if distance > self.written_samples {
+            return 0.0;
+        }
+        let index = (self.current_sample + self.max_delay_samples - distance) % self.max_delay_samples;
+        self.buffer[index]
+    }
+
+    // reads the buffer at a fractional distance from the write head. `allpass_state` holds the
+    // previous output of the read head and is only used by the allpass interpolator.
+    fn read_at(&self, delay: f32, allpass_state: &mut f32) -> f32 {
+        let delay = delay.clamp(MIN_DELAY_SAMPLES, self.max_delay_len());
+        let whole = delay.floor();
+        let frac = delay - whole;
+        let whole = whole as usize;
+
+        // x0 is the sample at the integer part of the delay, x1 is the one right before it in
+        // time, x_1 the one right after it and x2 the one two samples before it
+        let x0 = self.sample_at(whole);
+        let x1 = self.sample_at(whole + 1);
+
+        match self.interpolation {
+            Interpolation::Linear => x0 + frac * (x1 - x0),
+            Interpolation::Cubic => {
+                let x_1 = self.sample_at(whole - 1);
+                let x2 = self.sample_at(whole + 2);
+                // 4-point, 3rd-order Hermite
+                let c0 = x0;
+                let c1 = 0.5 * (x1 - x_1);
+                let c2 = x_1 - 2.5 * x0 + 2.0 * x1 - 0.5 * x2;
+                let c3 = 0.5 * (x2 - x_1) + 1.5 * (x0 - x1);
+                ((c3 * frac + c2) * frac + c1) * frac + c0
+            },
+            Interpolation::Allpass => {
+                // first order allpass, stable for the whole 0..1 range of the fraction. a
+                // fraction of 0 would put the pole on the unit circle, so shift it by a sample.
+                let (x0, x1, frac) = if frac < 0.1 {
+                    (self.sample_at(whole - 1), x0, frac + 1.0)
+                } else {
+                    (x0, x1, frac)
+                };
+                let eta = (1.0 - frac) / (1.0 + frac);
+                let y = eta * x0 + x1 - eta * *allpass_state;
+                *allpass_state = y;
+                y
+            },
+        }
     }
 
+    fn max_delay_len(&self) -> f32 {
+        (self.max_delay_samples.saturating_sub(GUARD_SAMPLES)) as f32
+    }
 
-    pub fn set_delay(&mut self, delay_time: DelayTime, delay_timing: DelayTiming, sample_rate: f32, bpm: f32) {
-        // dynamically resize the buffers if needed
-        if sample_rate != self.sample_rate || bpm != self.bpm {
-            self.resize_buffers(sample_rate, bpm);
-            self.sample_rate = sample_rate;
-            self.bpm = bpm;
+    // allocates the buffer for the longest possible delay, either tempo-synced at the lowest
+    // supported tempo, up to MAX_SYNCED_DELAY_MS, or free-running. must only be called outside of
+    // the audio thread.
+    pub fn resize_buffers(&mut self, sample_rate: f32) {
+        let synced_ms = (DelayTime::get_max_in_beats() * DelayTiming::get_max_multiplier() * 60000.0 / MIN_BPM).min(MAX_SYNCED_DELAY_MS);
+        let synced_len = (synced_ms / 1000.0 * sample_rate) as usize;
+        let free_len = (MAX_DELAY_MS / 1000.0 * sample_rate) as usize + 1;
+        let new_len = synced_len.max(free_len) + GUARD_SAMPLES;
+        self.max_delay_samples = new_len;
+        self.buffer = vec![0.0; new_len];
+        self.current_sample = 0;
+        self.written_samples = 0;
+        self.sample_rate = sample_rate;
+        self.filter.set_sample_rate(sample_rate);
+        self.low_cut.set_sample_rate(sample_rate);
+        self.high_cut.set_sample_rate(sample_rate);
+        self.pitch_shifter.resize_buffers(sample_rate);
+        self.saturator.set_sample_rate(sample_rate);
+        self.diffuser.set_sample_rate(sample_rate);
+        self.limiter.set_sample_rate(sample_rate);
+        self.freeze_step = 1.0 / (FREEZE_RAMP_MS / 1000.0 * sample_rate);
+        for tap in self.taps.iter_mut() {
+            tap.filter.set_sample_rate(sample_rate);
         }
-        self.delay_time = delay_time;
-        self.delay_timing = delay_timing;
+    }
+
+    pub fn set_delay(&mut self, delay_time: DelayTime, delay_timing: DelayTiming, bpm: f32, beats_per_bar: f32) {
+        self.head.target_delay_samples = self.synced_delay_samples(delay_time, delay_timing, bpm, beats_per_bar);
+    }
+
+    pub fn set_delay_ms(&mut self, delay_ms: f32) {
+        self.head.target_delay_samples = self.free_delay_samples(delay_ms);
+    }
+
+    fn synced_delay_samples(&self, delay_time: DelayTime, delay_timing: DelayTiming, bpm: f32, beats_per_bar: f32) -> f32 {
         //Delay (in samples) = (Delay Time (in beats) * 60 * Sample Rate) / BPM
-        // ((delay_time.get_delay_time() * 60.0 * sample_rate) / bpm as f32) as usize;
-        self.delay_samples = match delay_timing {
-            DelayTiming::Dotted => ((delay_time.get_delay_time_in_beats() * 1.5 * 60.0 * sample_rate) / bpm as f32) as usize,
-            DelayTiming::Triplet => ((delay_time.get_delay_time_in_beats() * 0.75 * 60.0 * sample_rate) / bpm as f32) as usize,
-            DelayTiming::Straight => ((delay_time.get_delay_time_in_beats() * 60.0 * sample_rate) / bpm as f32) as usize,
+        let delay_samples = (delay_time.get_delay_time_in_beats(beats_per_bar) * delay_timing.get_multiplier() * 60.0 * self.sample_rate) / bpm;
+        self.adjust_delay(delay_samples)
+    }
+
+    fn free_delay_samples(&self, delay_ms: f32) -> f32 {
+        // the buffer is always large enough for MAX_DELAY_MS, so no resizing is needed here
+        let delay_samples = delay_ms.clamp(0.0, MAX_DELAY_MS) / 1000.0 * self.sample_rate;
+        self.adjust_delay(delay_samples)
+    }
+
+    // applies this channel's time offset and keeps the delay within the buffer
+    fn adjust_delay(&self, delay_samples: f32) -> f32 {
+        let delay_samples = delay_samples * self.time_scale + self.time_offset_samples;
+        delay_samples.clamp(MIN_DELAY_SAMPLES, self.max_delay_len())
+    }
+
+    // 0 taps means the regular single read head is used. the delays of the taps should be set
+    // before, so taps that come back in start right at their delay.
+    pub fn set_num_taps(&mut self, num_taps: usize) {
+        let num_taps = num_taps.min(MAX_TAPS);
+        // taps that come back in start from a clean filter state
+        for tap in self.taps[self.num_taps.min(num_taps)..num_taps].iter_mut() {
+            tap.filter.reset_filter();
+            tap.head.reset();
+        }
+        self.num_taps = num_taps;
+    }
+
+    pub fn set_tap_delay(&mut self, index: usize, delay_time: DelayTime, delay_timing: DelayTiming, bpm: f32, beats_per_bar: f32) {
+        self.taps[index].head.target_delay_samples = self.synced_delay_samples(delay_time, delay_timing, bpm, beats_per_bar);
+    }
+
+    pub fn set_tap_delay_ms(&mut self, index: usize, delay_ms: f32) {
+        self.taps[index].head.target_delay_samples = self.free_delay_samples(delay_ms);
+    }
+
+    pub fn set_tap(&mut self, index: usize, gain: f32, pan_gain: f32, feedback_source: bool) {
+        let tap = &mut self.taps[index];
+        tap.gain = gain;
+        tap.pan_gain = pan_gain;
+        tap.feedback_source = feedback_source;
+    }
+
+    // like the feedback filter, the coefficients are only recalculated when something changed
+    pub fn set_tap_filter(&mut self, index: usize, filter_type: FilterType, cutoff: f32, resonance: f32) {
+        let cutoff = cutoff.min(0.49 * self.sample_rate);
+        self.taps[index].filter.set(FilterEngine::Biquad, filter_type, cutoff, resonance, 0.0);
+    }
+
+    // scales this channel's delay time and shifts it by a fixed amount, e.g. to spread the left
+    // and right repeats apart. takes effect on the next `set_delay` or `set_delay_ms` call.
+    pub fn set_time_offset(&mut self, scale: f32, offset_ms: f32) {
+        self.time_scale = scale;
+        self.time_offset_samples = offset_ms / 1000.0 * self.sample_rate;
+    }
+
+    pub fn set_shimmer(&mut self, semitones: f32, mix: f32, grain_ms: f32) {
+        self.pitch_shifter.set_params(semitones, mix, grain_ms);
+    }
+
+    pub fn set_diffusion(&mut self, amount: f32, size: f32, num_stages: usize, position: DiffusionPosition) {
+        self.diffuser.set_params(amount, size, num_stages);
+        self.diffusion_position = position;
+    }
+
+    pub fn set_saturation(&mut self, curve: SaturationCurve, drive_db: f32, mix: f32, oversampling: Oversampling) {
+        self.saturator.set_params(curve, drive_db, mix, oversampling);
+    }
+
+    // offsets all read heads by a (modulated) number of samples
+    pub fn set_modulation(&mut self, modulation_samples: f32) {
+        self.modulation_samples = modulation_samples;
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback;
+    }
+
+    pub fn set_limiter_ceiling(&mut self, ceiling_db: f32) {
+        self.limiter.set_ceiling(ceiling_db);
+    }
+
+    pub fn set_filter(&mut self, engine: FilterEngine, filter_type: FilterType, cutoff: f32, resonance: f32, gain: f32) {
+        self.filter.set(engine, filter_type, cutoff, resonance, gain);
+        self.filter_cutoff = cutoff;
+    }
+
+    pub fn set_low_cut(&mut self, enabled: bool, cutoff: f32, resonance: f32, slope: FilterSlope) {
+        self.low_cut.set(enabled, cutoff, resonance, slope);
+    }
+
+    pub fn set_high_cut(&mut self, enabled: bool, cutoff: f32, resonance: f32, slope: FilterSlope) {
+        self.high_cut.set(enabled, cutoff, resonance, slope);
+    }
+
+    // combined response of everything filtering the feedback
+    fn loop_filter_magnitude(&self, frequency: f32) -> f32 {
+        self.filter.get_magnitude(frequency) * self.low_cut.get_magnitude(frequency) * self.high_cut.get_magnitude(frequency)
+    }
+
+    // the highest value `magnitude` reaches anywhere in the audible range. resonant peaks sit at
+    // the cutoffs, so those get checked in addition to the log-spaced grid.
+    fn peak_gain(&self, cutoffs: &[f32], magnitude: impl Fn(f32) -> f32) -> f32 {
+        let nyquist = self.sample_rate / 2.0;
+        let mut peak = cutoffs.iter().fold(0.0_f32, |peak, cutoff| peak.max(magnitude(cutoff.min(nyquist * 0.999))));
+        for step in 0..PEAK_SEARCH_STEPS {
+            let frequency = 20.0 * (nyquist / 20.0).powf(step as f32 / (PEAK_SEARCH_STEPS - 1) as f32);
+            peak = peak.max(magnitude(frequency.min(nyquist * 0.999)));
+        }
+        peak
+    }
+
+    // the highest gain the feedback filters apply anywhere in the audible range
+    fn filter_peak_gain(&self) -> f32 {
+        let cutoffs = [self.filter_cutoff, self.low_cut.get_cutoff(), self.high_cut.get_cutoff()];
+        self.peak_gain(&cutoffs, |frequency| self.loop_filter_magnitude(frequency))
+    }
+
+    // the taps that feed back add up, each one scaled by its level and the peak of its filter
+    fn feedback_taps_peak_gain(&self) -> f32 {
+        let feedback_taps = self.taps[..self.num_taps].iter().filter(|tap| tap.feedback_source);
+        feedback_taps
+            .map(|tap| tap.gain.abs() * self.peak_gain(&[tap.filter.get_cutoff()], |frequency| tap.filter.get_magnitude(frequency)))
+            .sum()
+    }
+
+    // number of samples it takes for the repeats to decay below TAIL_SILENCE_DB, or `None` if the
+    // loop gain is at or above unity and they never do
+    pub fn get_tail_samples(&self) -> Option<f32> {
+        if self.frozen || self.freeze_amount > 0.0 {
+            return None;
+        }
+        let mut loop_gain = self.feedback.abs() * self.filter_peak_gain();
+        if self.num_taps > 0 {
+            loop_gain *= self.feedback_taps_peak_gain();
+        }
+        if loop_gain >= 1.0 || loop_gain.is_nan() {
+            return None;
+        }
+        let repeats = if loop_gain > 0.0 {
+            (TAIL_SILENCE_DB / (20.0 * loop_gain.log10())).ceil()
+        } else {
+            0.0
+        };
+
+        // every repeat takes as long as the longest tap that feeds back, and the longest tap adds
+        // its echo after the last repeat
+        let (loop_samples, longest_samples) = if self.num_taps == 0 {
+            let delay_samples = self.head.delay_samples.max(self.get_delay_samples());
+            // the reverse heads reach back up to two chunks
+            let delay_samples = if self.reverse { 2.0 * delay_samples } else { delay_samples };
+            (delay_samples, delay_samples)
+        } else {
+            let active_taps = self.taps[..self.num_taps].iter().filter(|tap| tap.gain > 0.0);
+            active_taps.fold((0.0, 0.0), |(loop_samples, longest_samples), tap| {
+                let delay_samples = tap.head.delay_samples.max(tap.head.target_delay_samples);
+                let loop_samples = if tap.feedback_source { delay_samples.max(loop_samples) } else { loop_samples };
+                (loop_samples, delay_samples.max(longest_samples))
+            })
+        };
+        Some(repeats * loop_samples + longest_samples)
+    }
+
+    pub fn set_reverse(&mut self, reverse: bool, reverse_feedback: ReverseFeedback) {
+        self.reverse = reverse;
+        self.reverse_feedback = reverse_feedback;
+    }
+
+    pub fn set_freeze(&mut self, frozen: bool) {
+        if frozen && !self.frozen {
+            self.freeze_delay_samples = self.get_delay_samples().round() as usize;
+        }
+        self.frozen = frozen;
+    }
+
+    // silences the buffer and all filter and interpolator state without reallocating. the buffer
+    // can hold over a minute of audio, so instead of clearing it here the old samples are only
+    // marked as stale and get overwritten as the write head moves on.
+    pub fn reset(&mut self) {
+        self.written_samples = 0;
+        self.filter.reset_filter();
+        self.low_cut.reset_filter();
+        self.high_cut.reset_filter();
+        self.pitch_shifter.reset();
+        self.saturator.reset();
+        self.diffuser.reset();
+        self.limiter.reset();
+        self.freeze_amount = if self.frozen { 1.0 } else { 0.0 };
+        self.reverse_heads = [ReverseHead::new(0.0), ReverseHead::new(0.5 * MIN_DELAY_SAMPLES)];
+        self.reverse_phase = 0.0;
+        self.reverse_chunk_samples = MIN_DELAY_SAMPLES;
+        self.head.reset();
+        for tap in self.taps.iter_mut() {
+            tap.filter.reset_filter();
+            tap.head.reset();
+        }
+    }
+
+    pub fn set_time_change(&mut self, time_change: TimeChange, transition_ms: f32) {
+        self.time_change = time_change;
+        let transition_samples = (transition_ms / 1000.0 * self.sample_rate).max(1.0);
+        if transition_samples != self.transition_samples {
+            self.transition_samples = transition_samples;
+            // one pole glide, settles to within 1% of the target after the transition time
+            self.glide_coeff = 1.0 - (-5.0 / transition_samples).exp();
+        }
+    }
+
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        if interpolation != self.interpolation {
+            self.interpolation = interpolation;
+            self.head.allpass_state = 0.0;
+            self.head.crossfade_allpass_state = 0.0;
+            for tap in self.taps.iter_mut() {
+                tap.head.allpass_state = 0.0;
+                tap.head.crossfade_allpass_state = 0.0;
+            }
+        }
+    }
+
+    // the delay the read head is heading towards, which is where the repeats end up once a
+    // crossfade or glide has finished
+    pub fn get_delay_samples(&self) -> f32 {
+        self.head.target_delay_samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    // energy of the last `delay_samples` samples in the buffer, which is what the frozen loop repeats
+    fn loop_energy(delay: &Delay) -> f32 {
+        (1..=delay.freeze_delay_samples).map(|distance| delay.sample_at(distance).powi(2)).sum()
+    }
+
+    #[test]
+    fn frozen_loop_keeps_its_energy() {
+        let mut delay = Delay::new();
+        delay.resize_buffers(SAMPLE_RATE);
+        delay.set_delay_ms(123.4);
+        delay.set_feedback(0.8);
+        delay.set_limiter_ceiling(0.0);
+        delay.set_filter(FilterEngine::Biquad, FilterType::LowPass2, 5000.0, 1.5, 0.0);
+        delay.set_interpolation(Interpolation::Cubic);
+        delay.set_reverse(true, ReverseFeedback::Reversed);
+        // none of these may leak into the frozen loop
+        for index in 0..3 {
+            delay.set_tap_delay_ms(index, 50.0 * (index + 1) as f32);
+            delay.set_tap(index, 0.5, 1.0, true);
+            delay.set_tap_filter(index, FilterType::HighPass2, 1000.0, 0.707);
+        }
+        delay.set_num_taps(3);
+
+        let mut noise = 1_u32;
+        for sample in 0..SAMPLE_RATE as usize {
+            noise = noise.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            delay.set_modulation(20.0 * (sample as f32 * 0.001).sin());
+            delay.process(noise as f32 / u32::MAX as f32 - 0.5);
+        }
+
+        delay.set_freeze(true);
+        // let the freeze ramp finish, the input is ignored from here on
+        for sample in 0..SAMPLE_RATE as usize {
+            delay.set_modulation(20.0 * (sample as f32 * 0.001).sin());
+            delay.process(1.0);
+        }
+        let energy = loop_energy(&delay);
+        assert!(energy > 0.0);
+
+        for sample in 0..100 * delay.freeze_delay_samples {
+            delay.set_modulation(20.0 * (sample as f32 * 0.001).sin());
+            delay.process(1.0);
+        }
+        assert_eq!(loop_energy(&delay), energy);
+    }
+
+    #[test]
+    fn feedback_taps_add_up_in_the_tail() {
+        let mut delay = Delay::new();
+        delay.resize_buffers(SAMPLE_RATE);
+        delay.set_feedback(0.8);
+        delay.set_filter(FilterEngine::Biquad, FilterType::LowPass2, 20000.0, 0.707, 0.0);
+        for index in 0..2 {
+            delay.set_tap_delay_ms(index, 100.0 * (index + 1) as f32);
+            delay.set_tap(index, 1.0, 1.0, true);
+            delay.set_tap_filter(index, FilterType::LowPass2, 20000.0, 0.707);
+        }
+        delay.set_num_taps(2);
+        // two taps at full level feed back 160%, so the repeats never die out
+        assert_eq!(delay.get_tail_samples(), None);
+
+        delay.set_tap(1, 1.0, 1.0, false);
+        assert!(delay.get_tail_samples().is_some());
+
+        // a resonant tap filter pushes the single tap above unity again. the new setting gets
+        // picked up at control rate.
+        for _ in 0..64 {
+            delay.set_tap_filter(0, FilterType::LowPass2, 1000.0, 2.0);
+            delay.process(0.0);
         }
+        assert_eq!(delay.get_tail_samples(), None);
     }
 
-    pub fn get_delay_samples(&self) -> usize {
-        self.delay_samples
+    #[test]
+    fn reverse_windows_add_up_while_the_chunk_changes() {
+        let mut delay = Delay::new();
+        delay.resize_buffers(SAMPLE_RATE);
+        delay.set_limiter_ceiling(0.0);
+        delay.set_filter(FilterEngine::Biquad, FilterType::LowPass2, 20000.0, 0.707, 0.0);
+        delay.set_reverse(true, ReverseFeedback::Reversed);
+
+        // fill the buffer further back than the longest chunk reaches
+        delay.set_delay_ms(100.0);
+        for _ in 0..SAMPLE_RATE as usize {
+            delay.process(0.5);
+        }
+
+        // a constant input plays back at the same level however the chunks overlap
+        let chunks_ms = [100.0, 37.0, 250.0, 3.0, 180.0];
+        for delay_ms in chunks_ms.into_iter().cycle().take(50) {
+            delay.set_delay_ms(delay_ms);
+            for _ in 0..(0.013 * SAMPLE_RATE) as usize {
+                let y = delay.process(0.5);
+                assert!((y - 0.5).abs() < 1e-3, "{y} with {delay_ms} ms");
+            }
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,51 +1,95 @@
 use nih_plug::prelude::Enum;
 
+#[derive(Copy, Clone, PartialEq)]
+pub enum DelayMode {
+    Sync,
+    Free,
+}
+
+impl Enum for DelayMode {
+    fn variants() -> &'static [&'static str] {
+        &["Sync", "Free"]
+    }
+
+    fn ids() -> Option<&'static [&'static str]> {
+        Some(&["sync", "free"])
+    }
+
+    fn to_index(self) -> usize {
+        match self {
+            DelayMode::Sync => 0,
+            DelayMode::Free => 1,
+        }
+    }
+
+    fn from_index(index: usize) -> Self {
+        match index {
+            0 => DelayMode::Sync,
+            1 => DelayMode::Free,
+            _ => DelayMode::Sync,
+        }
+    }
+}
+
+// in musical order. sessions store the value by its id, so new values can go anywhere as long as
+// the existing ids stay the same.
 #[derive(Copy, Clone, PartialEq)]
 pub enum DelayTime {
+    _1_64,
     _1_32,
     _1_16,
     _1_8,
     _1_4,
     _1_2,
     _1,
+    _2,
+    _4,
 }
 
 impl Enum for DelayTime {
     fn variants() -> &'static [&'static str] {
-        &["1/32", "1/16", "1/8", "1/4", "1/2", "1"]
+        &["1/64", "1/32", "1/16", "1/8", "1/4", "1/2", "1", "2 Bars", "4 Bars"]
     }
 
     fn ids() -> Option<&'static [&'static str]> {
         Some(&[
+            "1/64",
             "1/32",
             "1/16",
             "1/8",
             "1/4",
             "1/2",
             "1",
+            "2-bars",
+            "4-bars",
         ])
     }
 
     fn to_index(self) -> usize {
         match self {
-            DelayTime::_1_32 => 0,
-            DelayTime::_1_16 => 1,
-            DelayTime::_1_8 => 2,
-            DelayTime::_1_4 => 3,
-            DelayTime::_1_2 => 4,
-            DelayTime::_1 => 5,
-
+            DelayTime::_1_64 => 0,
+            DelayTime::_1_32 => 1,
+            DelayTime::_1_16 => 2,
+            DelayTime::_1_8 => 3,
+            DelayTime::_1_4 => 4,
+            DelayTime::_1_2 => 5,
+            DelayTime::_1 => 6,
+            DelayTime::_2 => 7,
+            DelayTime::_4 => 8,
         }
     }
 
     fn from_index(index: usize) -> Self {
         match index {
-            0 => DelayTime::_1_32,
-            1 => DelayTime::_1_16,
-            2 => DelayTime::_1_8,
-            3 => DelayTime::_1_4,
-            4 => DelayTime::_1_2,
-            5 => DelayTime::_1,
+            0 => DelayTime::_1_64,
+            1 => DelayTime::_1_32,
+            2 => DelayTime::_1_16,
+            3 => DelayTime::_1_8,
+            4 => DelayTime::_1_4,
+            5 => DelayTime::_1_2,
+            6 => DelayTime::_1,
+            7 => DelayTime::_2,
+            8 => DelayTime::_4,
             _ => DelayTime::_1_4,
         }
     }
@@ -54,61 +98,556 @@ impl Enum for DelayTime {
 impl DelayTime {
     pub fn get_delay_time_in_bars(&self) -> f32 {
         match self {
+            DelayTime::_1_64 => 1.0 / 64.0,
             DelayTime::_1_32 => 1.0 / 32.0,
             DelayTime::_1_16 => 1.0 / 16.0,
             DelayTime::_1_8 => 1.0 / 8.0,
             DelayTime::_1_4 => 1.0 / 4.0,
             DelayTime::_1_2 => 1.0 / 2.0,
             DelayTime::_1 => 1.0,
+            DelayTime::_2 => 2.0,
+            DelayTime::_4 => 4.0,
         }
     }
 
-    pub fn get_delay_time_in_beats(&self) -> f32 {
+    // beats are quarter notes, like the host's tempo. the note values are fixed, but from half a
+    // bar upwards the values are relative to the bar length given by the time signature.
+    pub fn get_delay_time_in_beats(&self, beats_per_bar: f32) -> f32 {
         match self {
+            DelayTime::_1_64 => 1.0 / 16.0,
             DelayTime::_1_32 => 1.0 / 8.0,
             DelayTime::_1_16 => 1.0 / 4.0,
             DelayTime::_1_8 => 1.0 / 2.0,
             DelayTime::_1_4 => 1.0,
-            DelayTime::_1_2 => 2.0,
-            DelayTime::_1 => 4.0,
+            DelayTime::_1_2 | DelayTime::_1 | DelayTime::_2 | DelayTime::_4 => {
+                self.get_delay_time_in_bars() * beats_per_bar
+            },
         }
     }
 
+    // the longest value in 4/4, which the buffers are sized for up to a fixed maximum. anything
+    // longer gets clamped to the buffer length.
     pub fn get_max_in_beats() -> f32 {
-        4.0
+        16.0
+    }
+}
+
+// length of a bar in quarter notes, e.g. 3 in 3/4 and 3.5 in 7/8
+pub fn get_beats_per_bar(numerator: i32, denominator: i32) -> f32 {
+    numerator as f32 * 4.0 / denominator as f32
+}
+
+// the denominator of the fallback time signature
+#[derive(Copy, Clone, PartialEq)]
+pub enum BeatUnit {
+    Half,
+    Quarter,
+    Eighth,
+    Sixteenth,
+}
+
+impl Enum for BeatUnit {
+    fn variants() -> &'static [&'static str] {
+        &["2", "4", "8", "16"]
+    }
+
+    fn ids() -> Option<&'static [&'static str]> {
+        Some(&["half", "quarter", "eighth", "sixteenth"])
+    }
+
+    fn to_index(self) -> usize {
+        match self {
+            BeatUnit::Half => 0,
+            BeatUnit::Quarter => 1,
+            BeatUnit::Eighth => 2,
+            BeatUnit::Sixteenth => 3,
+        }
+    }
+
+    fn from_index(index: usize) -> Self {
+        match index {
+            0 => BeatUnit::Half,
+            1 => BeatUnit::Quarter,
+            2 => BeatUnit::Eighth,
+            3 => BeatUnit::Sixteenth,
+            _ => BeatUnit::Quarter,
+        }
+    }
+}
+
+impl BeatUnit {
+    pub fn get_denominator(&self) -> i32 {
+        match self {
+            BeatUnit::Half => 2,
+            BeatUnit::Quarter => 4,
+            BeatUnit::Eighth => 8,
+            BeatUnit::Sixteenth => 16,
+        }
     }
 }
 
+// same as `DelayTime`, ordered musically and stored by id
 #[derive(Copy, Clone, PartialEq)]
 pub enum DelayTiming {
+    Straight,
     Dotted,
+    DoubleDotted,
     Triplet,
-    Straight,
+    Quintuplet,
+    Septuplet,
 }
 
 impl Enum for DelayTiming {
     fn variants() -> &'static [&'static str] {
-        &["Dotted", "Triplet", "Straight"]
+        &["Straight", "Dotted", "Double Dotted", "Triplet", "Quintuplet", "Septuplet"]
     }
 
     fn ids() -> Option<&'static [&'static str]> {
-        Some(&["dotted", "triplet", "straight"])
+        Some(&["straight", "dotted", "double-dotted", "triplet", "quintuplet", "septuplet"])
     }
 
     fn to_index(self) -> usize {
         match self {
-            DelayTiming::Dotted => 0,
-            DelayTiming::Triplet => 1,
-            DelayTiming::Straight => 2,
+            DelayTiming::Straight => 0,
+            DelayTiming::Dotted => 1,
+            DelayTiming::DoubleDotted => 2,
+            DelayTiming::Triplet => 3,
+            DelayTiming::Quintuplet => 4,
+            DelayTiming::Septuplet => 5,
         }
     }
 
     fn from_index(index: usize) -> Self {
         match index {
-            0 => DelayTiming::Dotted,
-            1 => DelayTiming::Triplet,
-            2 => DelayTiming::Straight,
+            0 => DelayTiming::Straight,
+            1 => DelayTiming::Dotted,
+            2 => DelayTiming::DoubleDotted,
+            3 => DelayTiming::Triplet,
+            4 => DelayTiming::Quintuplet,
+            5 => DelayTiming::Septuplet,
             _ => DelayTiming::Straight,
         }
     }
-}
\ No newline at end of file
+}
+
+impl DelayTiming {
+    // factor the straight note length gets multiplied with. tuplets fit n notes into the space of
+    // the next lower power of two, e.g. three triplet eighths last as long as two straight ones.
+    pub fn get_multiplier(&self) -> f32 {
+        match self {
+            DelayTiming::Straight => 1.0,
+            DelayTiming::Dotted => 1.5,
+            DelayTiming::DoubleDotted => 1.75,
+            DelayTiming::Triplet => 2.0 / 3.0,
+            DelayTiming::Quintuplet => 4.0 / 5.0,
+            DelayTiming::Septuplet => 4.0 / 7.0,
+        }
+    }
+
+    pub fn get_max_multiplier() -> f32 {
+        1.75
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    Cubic,
+    Allpass,
+}
+
+impl Enum for Interpolation {
+    fn variants() -> &'static [&'static str] {
+        &["Linear", "Cubic", "Allpass"]
+    }
+
+    fn ids() -> Option<&'static [&'static str]> {
+        Some(&["linear", "cubic", "allpass"])
+    }
+
+    fn to_index(self) -> usize {
+        match self {
+            Interpolation::Linear => 0,
+            Interpolation::Cubic => 1,
+            Interpolation::Allpass => 2,
+        }
+    }
+
+    fn from_index(index: usize) -> Self {
+        match index {
+            0 => Interpolation::Linear,
+            1 => Interpolation::Cubic,
+            2 => Interpolation::Allpass,
+            _ => Interpolation::Linear,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum TimeChange {
+    Jump,
+    Crossfade,
+    Tape,
+}
+
+impl Enum for TimeChange {
+    fn variants() -> &'static [&'static str] {
+        &["Jump", "Crossfade", "Tape"]
+    }
+
+    fn ids() -> Option<&'static [&'static str]> {
+        Some(&["jump", "crossfade", "tape"])
+    }
+
+    fn to_index(self) -> usize {
+        match self {
+            TimeChange::Jump => 0,
+            TimeChange::Crossfade => 1,
+            TimeChange::Tape => 2,
+        }
+    }
+
+    fn from_index(index: usize) -> Self {
+        match index {
+            0 => TimeChange::Jump,
+            1 => TimeChange::Crossfade,
+            2 => TimeChange::Tape,
+            _ => TimeChange::Jump,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum StereoMode {
+    Stereo,
+    PingPong,
+    CrossFeedback,
+    Mono,
+}
+
+impl Enum for StereoMode {
+    fn variants() -> &'static [&'static str] {
+        &["Stereo", "Ping-Pong", "Cross-Feedback", "Mono"]
+    }
+
+    fn ids() -> Option<&'static [&'static str]> {
+        Some(&["stereo", "ping-pong", "cross-feedback", "mono"])
+    }
+
+    fn to_index(self) -> usize {
+        match self {
+            StereoMode::Stereo => 0,
+            StereoMode::PingPong => 1,
+            StereoMode::CrossFeedback => 2,
+            StereoMode::Mono => 3,
+        }
+    }
+
+    fn from_index(index: usize) -> Self {
+        match index {
+            0 => StereoMode::Stereo,
+            1 => StereoMode::PingPong,
+            2 => StereoMode::CrossFeedback,
+            3 => StereoMode::Mono,
+            _ => StereoMode::Stereo,
+        }
+    }
+}
+
+// what happens to the repeats when the host transport stops
+#[derive(Copy, Clone, PartialEq)]
+pub enum StopBehaviour {
+    Ring,
+    Freeze,
+    Clear,
+}
+
+impl Enum for StopBehaviour {
+    fn variants() -> &'static [&'static str] {
+        &["Keep Ringing", "Freeze", "Clear"]
+    }
+
+    fn ids() -> Option<&'static [&'static str]> {
+        Some(&["ring", "freeze", "clear"])
+    }
+
+    fn to_index(self) -> usize {
+        match self {
+            StopBehaviour::Ring => 0,
+            StopBehaviour::Freeze => 1,
+            StopBehaviour::Clear => 2,
+        }
+    }
+
+    fn from_index(index: usize) -> Self {
+        match index {
+            0 => StopBehaviour::Ring,
+            1 => StopBehaviour::Freeze,
+            2 => StopBehaviour::Clear,
+            _ => StopBehaviour::Ring,
+        }
+    }
+}
+
+// what the reverse mode feeds back: its own reversed output, so every repeat flips direction
+// again, or the forward signal, so every repeat plays backwards
+#[derive(Copy, Clone, PartialEq)]
+pub enum ReverseFeedback {
+    Reversed,
+    Forward,
+}
+
+impl Enum for ReverseFeedback {
+    fn variants() -> &'static [&'static str] {
+        &["Reverse of Reverse", "Forward"]
+    }
+
+    fn ids() -> Option<&'static [&'static str]> {
+        Some(&["reversed", "forward"])
+    }
+
+    fn to_index(self) -> usize {
+        match self {
+            ReverseFeedback::Reversed => 0,
+            ReverseFeedback::Forward => 1,
+        }
+    }
+
+    fn from_index(index: usize) -> Self {
+        match index {
+            0 => ReverseFeedback::Reversed,
+            1 => ReverseFeedback::Forward,
+            _ => ReverseFeedback::Reversed,
+        }
+    }
+}
+
+// grain length of the shimmer's pitch shifter. limited to short note values, straight and
+// triplet, which all fit into the shifter's buffer down to MIN_BPM.
+#[derive(Copy, Clone, PartialEq)]
+pub enum ShimmerGrain {
+    _1_64,
+    _1_32T,
+    _1_32,
+    _1_16T,
+    _1_16,
+}
+
+impl Enum for ShimmerGrain {
+    fn variants() -> &'static [&'static str] {
+        &["1/64", "1/32 T", "1/32", "1/16 T", "1/16"]
+    }
+
+    fn ids() -> Option<&'static [&'static str]> {
+        Some(&["1/64", "1/32t", "1/32", "1/16t", "1/16"])
+    }
+
+    fn to_index(self) -> usize {
+        match self {
+            ShimmerGrain::_1_64 => 0,
+            ShimmerGrain::_1_32T => 1,
+            ShimmerGrain::_1_32 => 2,
+            ShimmerGrain::_1_16T => 3,
+            ShimmerGrain::_1_16 => 4,
+        }
+    }
+
+    fn from_index(index: usize) -> Self {
+        match index {
+            0 => ShimmerGrain::_1_64,
+            1 => ShimmerGrain::_1_32T,
+            2 => ShimmerGrain::_1_32,
+            3 => ShimmerGrain::_1_16T,
+            4 => ShimmerGrain::_1_16,
+            _ => ShimmerGrain::_1_32,
+        }
+    }
+}
+
+impl ShimmerGrain {
+    pub fn get_grain_in_beats(&self) -> f32 {
+        match self {
+            ShimmerGrain::_1_64 => 1.0 / 16.0,
+            ShimmerGrain::_1_32T => 1.0 / 12.0,
+            ShimmerGrain::_1_32 => 1.0 / 8.0,
+            ShimmerGrain::_1_16T => 1.0 / 6.0,
+            ShimmerGrain::_1_16 => 1.0 / 4.0,
+        }
+    }
+}
+
+// where the diffuser sits: in the loop, so every repeat gets more smeared than the last, or on
+// the output only
+#[derive(Copy, Clone, PartialEq)]
+pub enum DiffusionPosition {
+    Feedback,
+    Wet,
+}
+
+impl Enum for DiffusionPosition {
+    fn variants() -> &'static [&'static str] {
+        &["Feedback", "Wet"]
+    }
+
+    fn ids() -> Option<&'static [&'static str]> {
+        Some(&["feedback", "wet"])
+    }
+
+    fn to_index(self) -> usize {
+        match self {
+            DiffusionPosition::Feedback => 0,
+            DiffusionPosition::Wet => 1,
+        }
+    }
+
+    fn from_index(index: usize) -> Self {
+        match index {
+            0 => DiffusionPosition::Feedback,
+            1 => DiffusionPosition::Wet,
+            _ => DiffusionPosition::Feedback,
+        }
+    }
+}
+
+// which signal pushes the wet signal down when ducking
+#[derive(Copy, Clone, PartialEq)]
+pub enum DuckingSource {
+    Input,
+    Sidechain,
+}
+
+impl Enum for DuckingSource {
+    fn variants() -> &'static [&'static str] {
+        &["Input", "Sidechain"]
+    }
+
+    fn ids() -> Option<&'static [&'static str]> {
+        Some(&["input", "sidechain"])
+    }
+
+    fn to_index(self) -> usize {
+        match self {
+            DuckingSource::Input => 0,
+            DuckingSource::Sidechain => 1,
+        }
+    }
+
+    fn from_index(index: usize) -> Self {
+        match index {
+            0 => DuckingSource::Input,
+            1 => DuckingSource::Sidechain,
+            _ => DuckingSource::Input,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delay::MIN_BPM;
+    use crate::pitch_shift::MAX_GRAIN_MS;
+
+    fn all<T: Enum>() -> impl Iterator<Item = T> {
+        (0..T::variants().len()).map(T::from_index)
+    }
+
+    #[test]
+    fn delay_time_indices_round_trip() {
+        for (index, delay_time) in all::<DelayTime>().enumerate() {
+            assert_eq!(delay_time.to_index(), index);
+        }
+        assert_eq!(DelayTime::variants().len(), DelayTime::ids().unwrap().len());
+    }
+
+    #[test]
+    fn delay_timing_indices_round_trip() {
+        for (index, delay_timing) in all::<DelayTiming>().enumerate() {
+            assert_eq!(delay_timing.to_index(), index);
+        }
+        assert_eq!(DelayTiming::variants().len(), DelayTiming::ids().unwrap().len());
+    }
+
+    #[test]
+    fn original_ids_stay_put() {
+        // sessions saved before the new values were added store these ids
+        let delay_times = [
+            (DelayTime::_1_32, "1/32"),
+            (DelayTime::_1_16, "1/16"),
+            (DelayTime::_1_8, "1/8"),
+            (DelayTime::_1_4, "1/4"),
+            (DelayTime::_1_2, "1/2"),
+            (DelayTime::_1, "1"),
+        ];
+        for (delay_time, id) in delay_times {
+            assert_eq!(DelayTime::ids().unwrap()[delay_time.to_index()], id);
+        }
+        let delay_timings = [
+            (DelayTiming::Dotted, "dotted"),
+            (DelayTiming::Triplet, "triplet"),
+            (DelayTiming::Straight, "straight"),
+        ];
+        for (delay_timing, id) in delay_timings {
+            assert_eq!(DelayTiming::ids().unwrap()[delay_timing.to_index()], id);
+        }
+    }
+
+    #[test]
+    fn delay_time_beats_match_bars() {
+        for delay_time in all::<DelayTime>() {
+            assert_eq!(delay_time.get_delay_time_in_beats(4.0), delay_time.get_delay_time_in_bars() * 4.0);
+        }
+    }
+
+    #[test]
+    fn bar_values_follow_time_signature() {
+        assert_eq!(get_beats_per_bar(4, 4), 4.0);
+        assert_eq!(get_beats_per_bar(3, 4), 3.0);
+        assert_eq!(get_beats_per_bar(6, 8), 3.0);
+        assert_eq!(get_beats_per_bar(7, 8), 3.5);
+
+        let three_four = get_beats_per_bar(3, 4);
+        assert_eq!(DelayTime::_1.get_delay_time_in_beats(three_four), 3.0);
+        assert_eq!(DelayTime::_1_2.get_delay_time_in_beats(three_four), 1.5);
+        assert_eq!(DelayTime::_4.get_delay_time_in_beats(three_four), 12.0);
+        // note values don't depend on the bar length
+        assert_eq!(DelayTime::_1_4.get_delay_time_in_beats(three_four), 1.0);
+        assert_eq!(DelayTime::_1_16.get_delay_time_in_beats(get_beats_per_bar(7, 8)), 0.25);
+    }
+
+    #[test]
+    fn tuplets_fill_the_straight_grid() {
+        // n tuplet quarters take as long as the given number of straight quarters
+        let quarter = DelayTime::_1_4.get_delay_time_in_beats(4.0);
+        let cases = [
+            (DelayTiming::Triplet, 3.0, 2.0),
+            (DelayTiming::Quintuplet, 5.0, 4.0),
+            (DelayTiming::Septuplet, 7.0, 4.0),
+        ];
+        for (delay_timing, notes, straight_notes) in cases {
+            let total = notes * quarter * delay_timing.get_multiplier();
+            assert!((total - straight_notes * quarter).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn dotted_notes_add_halves() {
+        let eighth = DelayTime::_1_8.get_delay_time_in_beats(4.0);
+        assert_eq!(eighth * DelayTiming::Dotted.get_multiplier(), eighth + eighth / 2.0);
+        assert_eq!(
+            eighth * DelayTiming::DoubleDotted.get_multiplier(),
+            eighth + eighth / 2.0 + eighth / 4.0
+        );
+    }
+
+    #[test]
+    fn maxima_cover_every_value() {
+        let max_beats = all::<DelayTime>().map(|t| t.get_delay_time_in_beats(4.0)).fold(0.0, f32::max);
+        let max_multiplier = all::<DelayTiming>().map(|t| t.get_multiplier()).fold(0.0, f32::max);
+        assert_eq!(DelayTime::get_max_in_beats(), max_beats);
+        assert_eq!(DelayTiming::get_max_multiplier(), max_multiplier);
+
+        // every grain has to fit into the pitch shifter's buffer at the slowest tempo
+        let max_grain = all::<ShimmerGrain>().map(|g| g.get_grain_in_beats()).fold(0.0, f32::max);
+        assert!(max_grain * 60000.0 / MIN_BPM <= MAX_GRAIN_MS);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::filter::BiquadFilter;
+
+pub const MAX_DIFFUSION_STAGES: usize = 8;
+// the stages are spread between these frequencies at the smallest size, bigger sizes move them
+// down where the allpasses delay the signal for longer
+const LOWEST_FREQUENCY: f32 = 200.0;
+const HIGHEST_FREQUENCY: f32 = 6000.0;
+
+// chain of allpass filters that smears each echo out in time without changing its spectrum.
+// every stage delays a different frequency region, so the repeats turn into a wash instead of
+// discrete copies.
+#[derive(Clone, Copy)]
+pub struct Diffuser {
+    stages: [BiquadFilter; MAX_DIFFUSION_STAGES],
+    num_stages: usize,
+    amount: f32,
+    size: f32,
+    sample_rate: f32,
+}
+
+impl Diffuser {
+    pub fn new() -> Self {
+        Diffuser {
+            stages: [BiquadFilter::new(); MAX_DIFFUSION_STAGES],
+            num_stages: 0,
+            amount: 0.0,
+            size: 0.0,
+            sample_rate: 44100.0,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        for stage in self.stages.iter_mut() {
+            stage.set_sample_rate(sample_rate);
+        }
+        self.update_stages();
+    }
+
+    // the coefficients are only recalculated when the size or the number of stages changes
+    pub fn set_params(&mut self, amount: f32, size: f32, num_stages: usize) {
+        self.amount = amount;
+        let num_stages = num_stages.min(MAX_DIFFUSION_STAGES);
+        if size != self.size || num_stages != self.num_stages {
+            self.size = size;
+            self.num_stages = num_stages;
+            self.update_stages();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for stage in self.stages.iter_mut() {
+            stage.reset_filter();
+        }
+    }
+
+    fn update_stages(&mut self) {
+        let scale = 1.0 - 0.95 * self.size;
+        // a higher q delays the region around the cutoff for longer
+        let q = 0.7 + 9.3 * self.size;
+        let nyquist = 0.49 * self.sample_rate;
+        let num_stages = self.num_stages.max(2);
+        for (index, stage) in self.stages.iter_mut().enumerate().take(self.num_stages) {
+            let position = index as f32 / (num_stages - 1) as f32;
+            let cutoff = (scale * LOWEST_FREQUENCY * (HIGHEST_FREQUENCY / LOWEST_FREQUENCY).powf(position)).min(nyquist);
+            // every fourth stage is a first order one, which bends the phase over a wider range
+            if index % 4 == 3 {
+                stage.first_order_allpass_coefficients(self.sample_rate, cutoff);
+            } else {
+                stage.second_order_allpass_coefficients(self.sample_rate, cutoff, q);
+            }
+        }
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        if self.amount <= 0.0 || self.num_stages == 0 {
+            return x;
+        }
+
+        let mut y = x;
+        for stage in self.stages.iter_mut().take(self.num_stages) {
+            y = stage.process_left(y);
+        }
+        (1.0 - self.amount) * x + self.amount * y
+    }
+}
@@ -0,0 +1,149 @@
+// how long the limiter takes to let go after a peak
+const LIMITER_RELEASE_MS: f32 = 80.0;
+// the ducking fades in over this range above the threshold instead of switching on at once
+const DUCKING_KNEE_DB: f32 = 6.0;
+// the wet signal never gets louder than this, +6 dBFS
+pub const OUTPUT_CEILING: f32 = 2.0;
+
+// peak limiter for the feedback loop. the envelope jumps straight to every new peak, so nothing
+// ever gets past the ceiling, and signals below it pass untouched.
+#[derive(Clone, Copy)]
+pub struct Limiter {
+    ceiling: f32,
+    envelope: f32,
+    release_coeff: f32,
+}
+
+impl Limiter {
+    pub fn new() -> Self {
+        Limiter {
+            ceiling: 1.0,
+            envelope: 0.0,
+            release_coeff: 0.0,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.release_coeff = (-1.0 / (LIMITER_RELEASE_MS / 1000.0 * sample_rate)).exp();
+    }
+
+    pub fn set_ceiling(&mut self, ceiling_db: f32) {
+        self.ceiling = 10.0_f32.powf(ceiling_db / 20.0);
+    }
+
+    pub fn reset(&mut self) {
+        self.envelope = 0.0;
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        // a NaN or infinity would stay in the loop forever, so it's dropped here
+        if !x.is_finite() {
+            self.reset();
+            return 0.0;
+        }
+
+        self.envelope = x.abs().max(self.envelope * self.release_coeff);
+        if self.envelope > self.ceiling {
+            x * self.ceiling / self.envelope
+        } else {
+            x
+        }
+    }
+}
+
+// envelope follower on a key signal that turns into a gain for the wet signal. the louder the key
+// gets above the threshold, the further the gain drops, down to `1 - amount`.
+pub struct Ducker {
+    envelope: f32,
+    threshold: f32,
+    attack_coeff: f32,
+    release_coeff: f32,
+}
+
+impl Ducker {
+    pub fn new() -> Self {
+        Ducker {
+            envelope: 0.0,
+            threshold: 1.0,
+            attack_coeff: 0.0,
+            release_coeff: 0.0,
+        }
+    }
+
+    pub fn set_params(&mut self, threshold_db: f32, attack_ms: f32, release_ms: f32, sample_rate: f32) {
+        self.threshold = 10.0_f32.powf(threshold_db / 20.0);
+        self.attack_coeff = (-1.0 / (attack_ms / 1000.0 * sample_rate).max(1.0)).exp();
+        self.release_coeff = (-1.0 / (release_ms / 1000.0 * sample_rate).max(1.0)).exp();
+    }
+
+    pub fn reset(&mut self) {
+        self.envelope = 0.0;
+    }
+
+    // follows the key signal and returns the gain for the wet signal
+    pub fn process(&mut self, key: f32, amount: f32) -> f32 {
+        let level = if key.is_finite() { key.abs() } else { 0.0 };
+        let coeff = if level > self.envelope { self.attack_coeff } else { self.release_coeff };
+        self.envelope = level + (self.envelope - level) * coeff;
+
+        if self.envelope <= self.threshold {
+            return 1.0;
+        }
+        let over_db = 20.0 * (self.envelope / self.threshold).log10();
+        1.0 - amount * (over_db / DUCKING_KNEE_DB).min(1.0)
+    }
+}
+
+// last line of defence for the wet signal before it's mixed with the dry one: silences NaN and
+// infinity and hard clips everything else
+pub fn safety_clip(x: f32) -> f32 {
+    if x.is_finite() {
+        x.clamp(-OUTPUT_CEILING, OUTPUT_CEILING)
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    fn ducker() -> Ducker {
+        let mut ducker = Ducker::new();
+        ducker.set_params(-24.0, 1.0, 50.0, SAMPLE_RATE);
+        ducker
+    }
+
+    #[test]
+    fn silent_key_leaves_the_wet_signal_alone() {
+        let mut ducker = ducker();
+        for _ in 0..SAMPLE_RATE as usize {
+            assert_eq!(ducker.process(0.0, 1.0), 1.0);
+        }
+    }
+
+    #[test]
+    fn loud_key_ducks_by_the_amount_and_recovers() {
+        let mut ducker = ducker();
+        let mut gain = 1.0;
+        for _ in 0..(0.1 * SAMPLE_RATE) as usize {
+            gain = ducker.process(1.0, 0.75);
+        }
+        assert!((gain - 0.25).abs() < 1e-6);
+
+        for _ in 0..SAMPLE_RATE as usize {
+            gain = ducker.process(0.0, 0.75);
+        }
+        assert_eq!(gain, 1.0);
+    }
+
+    #[test]
+    fn non_finite_key_counts_as_silence() {
+        let mut ducker = ducker();
+        for key in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert_eq!(ducker.process(key, 1.0), 1.0);
+        }
+    }
+}
@@ -75,6 +75,10 @@ pub(crate) fn create(
                     
                         HStack::new(cx, |cx| {
                             VStack::new(cx, |cx| {
+                                Label::new(cx, "Mode")
+                                .child_space(Stretch(1.0))
+                                .font_size(SMALL_TEXT_SIZE).height(Pixels(30.0));
+
                                 Label::new(cx, "Delay Time")
                                 .font_size(SMALL_TEXT_SIZE).height(Pixels(30.0))
                                 .child_space(Stretch(1.0));
@@ -82,16 +86,26 @@ pub(crate) fn create(
                                 Label::new(cx, "Timing")
                                 .child_space(Stretch(1.0))
                                 .font_size(SMALL_TEXT_SIZE).height(Pixels(30.0));
+
+                                Label::new(cx, "Time (ms)")
+                                .child_space(Stretch(1.0))
+                                .font_size(SMALL_TEXT_SIZE).height(Pixels(30.0));
                             })
                             .row_between(Pixels(5.0))
                             .child_left(Pixels(10.0));
                             
                             VStack::new(cx, |cx| {
+                                ParamSlider::new(cx, Data::plugin_data, |params| &params.delay_mode)
+                                .height(Pixels(30.0));
+
                                 ParamSlider::new(cx, Data::plugin_data, |params| &params.delay_time)
                                 .height(Pixels(30.0));
 
                                 ParamSlider::new(cx, Data::plugin_data, |params| &params.delay_timing)
                                 .height(Pixels(30.0));
+
+                                ParamSlider::new(cx, Data::plugin_data, |params| &params.delay_ms)
+                                .height(Pixels(30.0));
                             })
                             .row_between(Pixels(5.0))
                             .child_right(Pixels(10.0));
@@ -183,6 +197,14 @@ pub(crate) fn create(
                                 Label::new(cx, "Dry")
                                 .child_space(Stretch(1.0))
                                 .font_size(SMALL_TEXT_SIZE).height(Pixels(30.0));
+
+                                Label::new(cx, "Stereo")
+                                .child_space(Stretch(1.0))
+                                .font_size(SMALL_TEXT_SIZE).height(Pixels(30.0));
+
+                                Label::new(cx, "Cross Feedback")
+                                .child_space(Stretch(1.0))
+                                .font_size(SMALL_TEXT_SIZE).height(Pixels(30.0));
                             })
                             .row_between(Pixels(5.0))
                             .child_left(Pixels(10.0));
@@ -193,6 +215,12 @@ pub(crate) fn create(
 
                                 ParamSlider::new(cx, Data::plugin_data, |params| &params.dry)
                                 .height(Pixels(30.0));
+
+                                ParamSlider::new(cx, Data::plugin_data, |params| &params.stereo_mode)
+                                .height(Pixels(30.0));
+
+                                ParamSlider::new(cx, Data::plugin_data, |params| &params.cross_feedback)
+                                .height(Pixels(30.0));
                             })
                             .row_between(Pixels(5.0))
                             .child_right(Pixels(10.0));
@@ -204,6 +232,19 @@ pub(crate) fn create(
                     .background_color(PANEL_COLOR);
 
                 }).col_between(Pixels(10.0));
+
+                HStack::new(cx, |cx| {
+                    ParamButton::new(cx, Data::plugin_data, |params| &params.freeze)
+                    .height(Pixels(30.0))
+                    .width(Pixels(PANEL_WIDTH));
+
+                    ParamButton::new(cx, Data::plugin_data, |params| &params.clear_buffer)
+                    .height(Pixels(30.0))
+                    .width(Pixels(PANEL_WIDTH));
+                })
+                .col_between(Pixels(10.0))
+                .height(Pixels(30.0))
+                .top(Pixels(10.0));
                 
             })
             .child_left(Stretch(1.0))
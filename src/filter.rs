@@ -1,4 +1,4 @@
-use std::f32::consts::PI;
+use std::f32::consts::{FRAC_1_SQRT_2, PI};
 
 use nih_plug::prelude::Enum;
 
@@ -7,6 +7,14 @@ pub enum FilterType {
     LowPass2,
     HighPass2,
     BandPass,
+    LowPass1,
+    HighPass1,
+    Notch,
+    AllPass1,
+    AllPass2,
+    LowShelf,
+    HighShelf,
+    Peak,
 }
 
 impl Enum for FilterType {
@@ -15,6 +23,14 @@ impl Enum for FilterType {
             "LPF",
             "HPF",
             "BPF",
+            "LPF 6dB",
+            "HPF 6dB",
+            "Notch",
+            "APF 1st",
+            "APF 2nd",
+            "Low Shelf",
+            "High Shelf",
+            "Peak",
         ]
     }
 
@@ -23,6 +39,14 @@ impl Enum for FilterType {
             "lp2",
             "hp2",
             "bp",
+            "lp1",
+            "hp1",
+            "notch",
+            "ap1",
+            "ap2",
+            "low-shelf",
+            "high-shelf",
+            "peak",
         ])
     }
 
@@ -31,6 +55,14 @@ impl Enum for FilterType {
             FilterType::LowPass2 => 0,
             FilterType::HighPass2 => 1,
             FilterType::BandPass => 2,
+            FilterType::LowPass1 => 3,
+            FilterType::HighPass1 => 4,
+            FilterType::Notch => 5,
+            FilterType::AllPass1 => 6,
+            FilterType::AllPass2 => 7,
+            FilterType::LowShelf => 8,
+            FilterType::HighShelf => 9,
+            FilterType::Peak => 10,
         }
     }
 
@@ -39,7 +71,15 @@ impl Enum for FilterType {
             0 => FilterType::LowPass2,
             1 => FilterType::HighPass2,
             2 => FilterType::BandPass,
-            _ => panic!("Invalid filter type index."),
+            3 => FilterType::LowPass1,
+            4 => FilterType::HighPass1,
+            5 => FilterType::Notch,
+            6 => FilterType::AllPass1,
+            7 => FilterType::AllPass2,
+            8 => FilterType::LowShelf,
+            9 => FilterType::HighShelf,
+            10 => FilterType::Peak,
+            _ => FilterType::LowPass2,
         }
     }
 
@@ -88,6 +128,12 @@ pub struct BiquadFilter {
     sample_rate: f32,
 }
 
+impl Default for BiquadFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BiquadFilter {
     pub fn new() -> Self {
         let coeffs = BiquadCoefficients::new(0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0);
@@ -176,6 +222,30 @@ impl BiquadFilter {
         }
     }
 
+    // magnitude of the frequency response at the given frequency, including the c0/d0 wet/dry
+    // mix used by the shelving filters
+    pub fn get_magnitude(&self, frequency: f32) -> f32 {
+        let w = 2.0 * PI * frequency / self.sample_rate;
+        let (cos1, sin1) = (w.cos(), w.sin());
+        let (cos2, sin2) = ((2.0 * w).cos(), (2.0 * w).sin());
+
+        // H(z) = (a0 + a1 z^-1 + a2 z^-2) / (1 + b0 z^-1 + b1 z^-2) evaluated at z = e^jw
+        let num_re = self.coeffs.a0 + self.coeffs.a1 * cos1 + self.coeffs.a2 * cos2;
+        let num_im = -(self.coeffs.a1 * sin1 + self.coeffs.a2 * sin2);
+        let den_re = 1.0 + self.coeffs.b0 * cos1 + self.coeffs.b1 * cos2;
+        let den_im = -(self.coeffs.b0 * sin1 + self.coeffs.b1 * sin2);
+        let den = den_re * den_re + den_im * den_im;
+        if den == 0.0 {
+            return f32::INFINITY;
+        }
+        let h_re = (num_re * den_re + num_im * den_im) / den;
+        let h_im = (num_im * den_re - num_re * den_im) / den;
+
+        let y_re = self.coeffs.c0 * h_re + self.coeffs.d0;
+        let y_im = self.coeffs.c0 * h_im;
+        (y_re * y_re + y_im * y_im).sqrt()
+    }
+
     // for use of 1st order allpass filters in phaser
     pub fn get_s_value(&self) -> f32 {
         // sum all coeficients from a1 to b2
@@ -356,6 +426,547 @@ impl BiquadFilter {
             FilterType::BandPass => {
                 self.band_pass_coefficients(self.sample_rate, cutoff, q);
             },
+            FilterType::LowPass1 => {
+                self.first_order_lpf_coefficients(self.sample_rate, cutoff);
+            },
+            FilterType::HighPass1 => {
+                self.first_order_hpf_coefficients(self.sample_rate, cutoff);
+            },
+            FilterType::Notch => {
+                self.notch_coefficients(self.sample_rate, cutoff, q);
+            },
+            FilterType::AllPass1 => {
+                self.first_order_allpass_coefficients(self.sample_rate, cutoff);
+            },
+            FilterType::AllPass2 => {
+                self.second_order_allpass_coefficients(self.sample_rate, cutoff, q);
+            },
+            FilterType::LowShelf => {
+                self.low_shelf_coefficients(self.sample_rate, cutoff, gain);
+            },
+            FilterType::HighShelf => {
+                self.high_shelf_coefficients(self.sample_rate, cutoff, gain);
+            },
+            FilterType::Peak => {
+                self.peak_coefficients(self.sample_rate, cutoff, q, gain);
+            },
+        }
+    }
+}
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FilterSlope {
+    Db12,
+    Db24,
+    Db48,
+}
+
+impl Enum for FilterSlope {
+    fn variants() -> &'static [&'static str] {
+        &[
+            "12 dB/oct",
+            "24 dB/oct",
+            "48 dB/oct",
+        ]
+    }
+
+    fn ids() -> Option<&'static [&'static str]> {
+        Some(&[
+            "12db",
+            "24db",
+            "48db",
+        ])
+    }
+
+    fn to_index(self) -> usize {
+        match self {
+            FilterSlope::Db12 => 0,
+            FilterSlope::Db24 => 1,
+            FilterSlope::Db48 => 2,
+        }
+    }
+
+    fn from_index(index: usize) -> Self {
+        match index {
+            0 => FilterSlope::Db12,
+            1 => FilterSlope::Db24,
+            2 => FilterSlope::Db48,
+            _ => FilterSlope::Db12,
+        }
+    }
+}
+
+impl FilterSlope {
+    // q values of the second order sections that make up a butterworth filter of this slope
+    fn get_section_qs(&self) -> &'static [f32] {
+        match self {
+            FilterSlope::Db12 => &[FRAC_1_SQRT_2],
+            FilterSlope::Db24 => &[0.5412, 1.3066],
+            FilterSlope::Db48 => &[0.5098, 0.6013, 0.9000, 2.5629],
+        }
+    }
+}
+
+// how often the coefficients follow a parameter that keeps changing, e.g. while smoothing or
+// under modulation
+const CONTROL_RATE_SAMPLES: u32 = 32;
+
+const MAX_CUT_SECTIONS: usize = 4;
+
+// low cut or high cut built from up to four cascaded second order sections. the resonance scales
+// the q of the last section, which is the one that shapes the knee, so 0.707 keeps it butterworth.
+#[derive(Clone, Copy)]
+pub struct CutFilter {
+    sections: [BiquadFilter; MAX_CUT_SECTIONS],
+    num_sections: usize,
+    high_pass: bool,
+    enabled: bool,
+    slope: FilterSlope,
+    cutoff: f32,
+    resonance: f32,
+    samples_since_update: u32,
+}
+
+impl CutFilter {
+    pub fn new(high_pass: bool) -> Self {
+        Self {
+            sections: [BiquadFilter::new(); MAX_CUT_SECTIONS],
+            num_sections: 1,
+            high_pass,
+            enabled: false,
+            slope: FilterSlope::Db12,
+            cutoff: if high_pass { 20.0 } else { 20000.0 },
+            resonance: f32::NAN,
+            samples_since_update: CONTROL_RATE_SAMPLES,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        for section in self.sections.iter_mut() {
+            section.set_sample_rate(sample_rate);
+        }
+        // force the next `set` to redesign for the new rate
+        self.resonance = f32::NAN;
+        self.samples_since_update = CONTROL_RATE_SAMPLES;
+    }
+
+    pub fn reset_filter(&mut self) {
+        for section in self.sections.iter_mut() {
+            section.reset_filter();
+        }
+    }
+
+    // like `FeedbackFilter::set`, only redesigns on changes and at the control rate
+    pub fn set(&mut self, enabled: bool, cutoff: f32, resonance: f32, slope: FilterSlope) {
+        self.samples_since_update = self.samples_since_update.saturating_add(1);
+        let switched = enabled != self.enabled || slope != self.slope;
+        let changed = cutoff != self.cutoff || resonance != self.resonance;
+        let due = changed && self.samples_since_update >= CONTROL_RATE_SAMPLES;
+        if !(switched || due) {
+            return;
+        }
+
+        // sections that were bypassed until now would start with stale state
+        if (enabled && !self.enabled) || slope != self.slope {
+            self.reset_filter();
+        }
+        self.enabled = enabled;
+        self.slope = slope;
+        self.cutoff = cutoff;
+        self.resonance = resonance;
+        self.samples_since_update = 0;
+        if !enabled {
+            return;
+        }
+
+        let qs = slope.get_section_qs();
+        self.num_sections = qs.len();
+        for (index, (section, q)) in self.sections.iter_mut().zip(qs.iter()).enumerate() {
+            let q = if index == qs.len() - 1 { q * resonance / FRAC_1_SQRT_2 } else { *q };
+            if self.high_pass {
+                section.coefficients(FilterType::HighPass2, cutoff, q, 0.0);
+            } else {
+                section.coefficients(FilterType::LowPass2, cutoff, q, 0.0);
+            }
+        }
+    }
+
+    pub fn get_cutoff(&self) -> f32 {
+        self.cutoff
+    }
+
+    pub fn process_left(&mut self, x: f32) -> f32 {
+        if !self.enabled {
+            return x;
+        }
+        self.sections[..self.num_sections]
+            .iter_mut()
+            .fold(x, |y, section| section.process_left(y))
+    }
+
+    pub fn get_magnitude(&self, frequency: f32) -> f32 {
+        if !self.enabled {
+            return 1.0;
+        }
+        self.sections[..self.num_sections]
+            .iter()
+            .map(|section| section.get_magnitude(frequency))
+            .product()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FilterEngine {
+    Biquad,
+    Svf,
+}
+
+impl Enum for FilterEngine {
+    fn variants() -> &'static [&'static str] {
+        &[
+            "Biquad",
+            "SVF",
+        ]
+    }
+
+    fn ids() -> Option<&'static [&'static str]> {
+        Some(&[
+            "biquad",
+            "svf",
+        ])
+    }
+
+    fn to_index(self) -> usize {
+        match self {
+            FilterEngine::Biquad => 0,
+            FilterEngine::Svf => 1,
         }
     }
-}
\ No newline at end of file
+
+    fn from_index(index: usize) -> Self {
+        match index {
+            0 => FilterEngine::Biquad,
+            1 => FilterEngine::Svf,
+            _ => FilterEngine::Biquad,
+        }
+    }
+}
+
+// zero delay feedback (topology preserving transform) state variable filter. the state holds
+// the integrator outputs rather than past samples, so the cutoff can be swept every sample
+// without the bursts a direct form biquad produces. the first order types run a one pole
+// integrator on the same state.
+#[derive(Clone, Copy)]
+pub struct SvfFilter {
+    filter_type: FilterType,
+    // g is the prewarped cutoff, k the damping (1 / q)
+    g: f32,
+    k: f32,
+    a1: f32,
+    a2: f32,
+    a3: f32,
+    // output mix of the input, band pass and low pass signals
+    m0: f32,
+    m1: f32,
+    m2: f32,
+    ic1eq: f32,
+    ic2eq: f32,
+    sample_rate: f32,
+}
+
+impl Default for SvfFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SvfFilter {
+    pub fn new() -> Self {
+        Self {
+            filter_type: FilterType::LowPass2,
+            g: 0.0,
+            k: 1.0,
+            a1: 1.0,
+            a2: 0.0,
+            a3: 0.0,
+            m0: 0.0,
+            m1: 0.0,
+            m2: 1.0,
+            ic1eq: 0.0,
+            ic2eq: 0.0,
+            sample_rate: 44100.0,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    pub fn reset_filter(&mut self) {
+        self.ic1eq = 0.0;
+        self.ic2eq = 0.0;
+    }
+
+    pub fn coefficients(&mut self, filter_type: FilterType, cutoff: f32, q: f32, gain: f32) {
+        let cutoff = cutoff.clamp(1.0, 0.49 * self.sample_rate);
+        let g = (PI * cutoff / self.sample_rate).tan();
+        let k = 1.0 / q;
+        // shelf and bell gain, as the square root of the linear gain
+        let a = 10.0_f32.powf(gain / 40.0);
+        let (g, k, m0, m1, m2) = match filter_type {
+            FilterType::LowPass2 => (g, k, 0.0, 0.0, 1.0),
+            FilterType::HighPass2 => (g, k, 1.0, -k, -1.0),
+            // scaled so the peak sits at 0 dB like the biquad version
+            FilterType::BandPass => (g, k, 0.0, k, 0.0),
+            FilterType::Notch => (g, k, 1.0, -k, 0.0),
+            FilterType::AllPass2 => (g, k, 1.0, -2.0 * k, 0.0),
+            FilterType::Peak => {
+                let k = 1.0 / (q * a);
+                (g, k, 1.0, k * (a * a - 1.0), 0.0)
+            },
+            FilterType::LowShelf => (g / a.sqrt(), k, 1.0, k * (a - 1.0), a * a - 1.0),
+            FilterType::HighShelf => (g * a.sqrt(), k, a * a, k * (1.0 - a) * a, 1.0 - a * a),
+            // the one pole types only use g, the mix picks input (m0) and low pass (m2)
+            FilterType::LowPass1 => (g, k, 0.0, 0.0, 1.0),
+            FilterType::HighPass1 => (g, k, 1.0, 0.0, -1.0),
+            FilterType::AllPass1 => (g, k, -1.0, 0.0, 2.0),
+        };
+        self.filter_type = filter_type;
+        self.g = g;
+        self.k = k;
+        self.a1 = 1.0 / (1.0 + g * (g + k));
+        self.a2 = g * self.a1;
+        self.a3 = g * self.a2;
+        self.m0 = m0;
+        self.m1 = m1;
+        self.m2 = m2;
+    }
+
+    fn is_first_order(&self) -> bool {
+        matches!(self.filter_type, FilterType::LowPass1 | FilterType::HighPass1 | FilterType::AllPass1)
+    }
+
+    pub fn process_left(&mut self, x: f32) -> f32 {
+        if self.is_first_order() {
+            let v = (x - self.ic1eq) * self.g / (1.0 + self.g);
+            let low = v + self.ic1eq;
+            self.ic1eq = low + v;
+            return self.m0 * x + self.m2 * low;
+        }
+
+        let v3 = x - self.ic2eq;
+        let v1 = self.a1 * self.ic1eq + self.a2 * v3;
+        let v2 = self.ic2eq + self.a2 * self.ic1eq + self.a3 * v3;
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+        self.m0 * x + self.m1 * v1 + self.m2 * v2
+    }
+
+    // the filter is a bilinear transform of the analog prototype, so its response at a frequency
+    // is the prototype's at the prewarped frequency
+    pub fn get_magnitude(&self, frequency: f32) -> f32 {
+        // s = jw / g
+        let w = (PI * frequency / self.sample_rate).tan() / self.g;
+        if self.is_first_order() {
+            // low pass 1 / (1 + s)
+            let den = 1.0 + w * w;
+            let (low_re, low_im) = (1.0 / den, -w / den);
+            let re = self.m0 + self.m2 * low_re;
+            let im = self.m2 * low_im;
+            return (re * re + im * im).sqrt();
+        }
+
+        // band pass s / (s² + ks + 1), low pass 1 / (s² + ks + 1)
+        let (den_re, den_im) = (1.0 - w * w, self.k * w);
+        let den = den_re * den_re + den_im * den_im;
+        if den == 0.0 {
+            return f32::INFINITY;
+        }
+        let (low_re, low_im) = (den_re / den, -den_im / den);
+        let (band_re, band_im) = (-w * low_im, w * low_re);
+        let re = self.m0 + self.m1 * band_re + self.m2 * low_re;
+        let im = self.m1 * band_im + self.m2 * low_im;
+        (re * re + im * im).sqrt()
+    }
+}
+
+// the feedback filter with either engine. the coefficients are only recalculated when a setting
+// changed, and at most once per CONTROL_RATE_SAMPLES while it keeps changing. a new filter type
+// or engine is picked up right away.
+#[derive(Clone, Copy)]
+pub struct FeedbackFilter {
+    biquad: BiquadFilter,
+    svf: SvfFilter,
+    engine: FilterEngine,
+    filter_type: FilterType,
+    cutoff: f32,
+    q: f32,
+    gain: f32,
+    samples_since_update: u32,
+}
+
+impl Default for FeedbackFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FeedbackFilter {
+    pub fn new() -> Self {
+        Self {
+            biquad: BiquadFilter::new(),
+            svf: SvfFilter::new(),
+            engine: FilterEngine::Biquad,
+            filter_type: FilterType::LowPass2,
+            // NaN never compares equal, so the first `set` always updates
+            cutoff: f32::NAN,
+            q: f32::NAN,
+            gain: f32::NAN,
+            samples_since_update: CONTROL_RATE_SAMPLES,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.biquad.set_sample_rate(sample_rate);
+        self.svf.set_sample_rate(sample_rate);
+        // force the next `set` to redesign for the new rate
+        self.cutoff = f32::NAN;
+        self.samples_since_update = CONTROL_RATE_SAMPLES;
+    }
+
+    pub fn reset_filter(&mut self) {
+        self.biquad.reset_filter();
+        self.svf.reset_filter();
+    }
+
+    pub fn set(&mut self, engine: FilterEngine, filter_type: FilterType, cutoff: f32, q: f32, gain: f32) {
+        self.samples_since_update = self.samples_since_update.saturating_add(1);
+        let switched = engine != self.engine || filter_type != self.filter_type;
+        let changed = cutoff != self.cutoff || q != self.q || gain != self.gain;
+        let due = changed && self.samples_since_update >= CONTROL_RATE_SAMPLES;
+        if !(switched || due) {
+            return;
+        }
+
+        if engine != self.engine {
+            // the other engine has been idle, don't let it resume from old state
+            self.reset_filter();
+        }
+        self.engine = engine;
+        self.filter_type = filter_type;
+        self.cutoff = cutoff;
+        self.q = q;
+        self.gain = gain;
+        self.samples_since_update = 0;
+        match engine {
+            FilterEngine::Biquad => self.biquad.coefficients(filter_type, cutoff, q, gain),
+            FilterEngine::Svf => self.svf.coefficients(filter_type, cutoff, q, gain),
+        }
+    }
+
+    pub fn get_cutoff(&self) -> f32 {
+        self.cutoff
+    }
+
+    pub fn process_left(&mut self, x: f32) -> f32 {
+        match self.engine {
+            FilterEngine::Biquad => self.biquad.process_left(x),
+            FilterEngine::Svf => self.svf.process_left(x),
+        }
+    }
+
+    pub fn get_magnitude(&self, frequency: f32) -> f32 {
+        match self.engine {
+            FilterEngine::Biquad => self.biquad.get_magnitude(frequency),
+            FilterEngine::Svf => self.svf.get_magnitude(frequency),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    // measures the magnitude at `frequency` by running a sine through the filter
+    fn measured_magnitude(mut process: impl FnMut(f32) -> f32, frequency: f32) -> f32 {
+        let settle = SAMPLE_RATE as usize / 2;
+        let mut peak: f32 = 0.0;
+        for n in 0..settle * 2 {
+            let y = process((2.0 * PI * frequency * n as f32 / SAMPLE_RATE).sin());
+            if n >= settle {
+                peak = peak.max(y.abs());
+            }
+        }
+        peak
+    }
+
+    #[test]
+    fn svf_matches_biquad_response() {
+        let db = |magnitude: f32| 20.0 * magnitude.max(1e-3).log10();
+        for index in 0..FilterType::variants().len() {
+            let filter_type = FilterType::from_index(index);
+            let mut biquad = BiquadFilter::new();
+            biquad.set_sample_rate(SAMPLE_RATE);
+            biquad.coefficients(filter_type, 1000.0, 0.707, 6.0);
+            let mut svf = SvfFilter::new();
+            svf.set_sample_rate(SAMPLE_RATE);
+            svf.coefficients(filter_type, 1000.0, 0.707, 6.0);
+
+            // the biquad shelves are first order and its peak defines q differently, so those
+            // designs only have to agree where the gain is fully applied or not at all. all the
+            // others are the same filters.
+            let frequencies: &[f32] = match filter_type {
+                FilterType::LowShelf | FilterType::HighShelf => &[20.0, 20000.0],
+                FilterType::Peak => &[20.0, 1000.0, 20000.0],
+                _ => &[20.0, 100.0, 500.0, 1000.0, 2000.0, 5000.0, 15000.0],
+            };
+            for &frequency in frequencies {
+                let expected = biquad.get_magnitude(frequency);
+                let predicted = svf.get_magnitude(frequency);
+                let measured = measured_magnitude(|x| svf.process_left(x), frequency);
+                assert!((predicted - measured).abs() < 0.01, "{index} at {frequency} Hz: {predicted} vs {measured}");
+                assert!((db(predicted) - db(expected)).abs() < 0.1, "{index} at {frequency} Hz: {predicted} vs {expected}");
+            }
+        }
+    }
+
+    #[test]
+    fn feedback_filter_follows_changes() {
+        let mut filter = FeedbackFilter::new();
+        filter.set_sample_rate(SAMPLE_RATE);
+        filter.set(FilterEngine::Biquad, FilterType::LowPass2, 1000.0, 0.707, 0.0);
+        assert!((filter.get_magnitude(1000.0) - FRAC_1_SQRT_2).abs() < 0.01);
+
+        // a new cutoff is picked up within one control period
+        for _ in 0..CONTROL_RATE_SAMPLES {
+            filter.set(FilterEngine::Biquad, FilterType::LowPass2, 4000.0, 0.707, 0.0);
+        }
+        assert!((filter.get_magnitude(4000.0) - FRAC_1_SQRT_2).abs() < 0.01);
+
+        // a new type or engine right away
+        filter.set(FilterEngine::Svf, FilterType::HighPass2, 4000.0, 0.707, 0.0);
+        assert!(filter.get_magnitude(100.0) < 0.01);
+    }
+
+    #[test]
+    fn cached_updates_match_per_sample_updates() {
+        // white noise, so every part of the spectrum is compared
+        let input = |n: usize| ((n as u32).wrapping_mul(1_103_515_245).wrapping_add(12345) >> 8) as f32 / 8_388_608.0 - 1.0;
+        // a sweep that stops half way through
+        let cutoff = |n: usize| 1000.0 + 500.0 * (n.min(24000) as f32 / 2000.0).sin();
+
+        let mut cached = FeedbackFilter::new();
+        cached.set_sample_rate(SAMPLE_RATE);
+        let mut per_sample = BiquadFilter::new();
+        per_sample.set_sample_rate(SAMPLE_RATE);
+        for n in 0..48000 {
+            cached.set(FilterEngine::Biquad, FilterType::LowPass2, cutoff(n), 0.707, 0.0);
+            per_sample.coefficients(FilterType::LowPass2, cutoff(n), 0.707, 0.0);
+            let difference = (cached.process_left(input(n)) - per_sample.process_left(input(n))).abs();
+            // at most one control period behind while sweeping, and the same once it has settled
+            let tolerance = if n < 24000 + 2000 { 0.05 } else { 1e-5 };
+            assert!(difference < tolerance, "{difference} at {n}");
+        }
+    }
+}
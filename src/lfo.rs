@@ -0,0 +1,104 @@
+use std::f32::consts::PI;
+
+use nih_plug::prelude::Enum;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+    SampleAndHold,
+}
+
+impl Enum for LfoShape {
+    fn variants() -> &'static [&'static str] {
+        &["Sine", "Triangle", "Saw", "Square", "Sample & Hold"]
+    }
+
+    fn ids() -> Option<&'static [&'static str]> {
+        Some(&["sine", "triangle", "saw", "square", "sample-and-hold"])
+    }
+
+    fn to_index(self) -> usize {
+        match self {
+            LfoShape::Sine => 0,
+            LfoShape::Triangle => 1,
+            LfoShape::Saw => 2,
+            LfoShape::Square => 3,
+            LfoShape::SampleAndHold => 4,
+        }
+    }
+
+    fn from_index(index: usize) -> Self {
+        match index {
+            0 => LfoShape::Sine,
+            1 => LfoShape::Triangle,
+            2 => LfoShape::Saw,
+            3 => LfoShape::Square,
+            4 => LfoShape::SampleAndHold,
+            _ => LfoShape::Sine,
+        }
+    }
+}
+
+pub struct Lfo {
+    // position within the current cycle, 0..1
+    phase: f32,
+    phase_increment: f32,
+    shape: LfoShape,
+    // the random value the sample & hold shape outputs for the current cycle
+    held_value: f32,
+    // seeded up front so the audio thread never has to initialize a random generator
+    rng: StdRng,
+}
+
+impl Lfo {
+    pub fn new() -> Self {
+        let mut rng = StdRng::seed_from_u64(0x6d61_6572_6f72);
+        let held_value = rng.gen_range(-1.0..=1.0);
+        Lfo {
+            phase: 0.0,
+            phase_increment: 0.0,
+            shape: LfoShape::Sine,
+            held_value,
+            rng,
+        }
+    }
+
+    pub fn set_shape(&mut self, shape: LfoShape) {
+        self.shape = shape;
+    }
+
+    // sets the length of one cycle
+    pub fn set_period(&mut self, period_samples: f32) {
+        self.phase_increment = 1.0 / period_samples.max(1.0);
+    }
+
+    // restarts the cycle, e.g. when the host transport starts
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+        self.held_value = self.rng.gen_range(-1.0..=1.0);
+    }
+
+    // returns the current value in the -1..1 range and advances by one sample
+    pub fn next(&mut self) -> f32 {
+        let phase = self.phase;
+        let y = match self.shape {
+            LfoShape::Sine => (2.0 * PI * phase).sin(),
+            LfoShape::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            LfoShape::Saw => 2.0 * phase - 1.0,
+            LfoShape::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            LfoShape::SampleAndHold => self.held_value,
+        };
+
+        self.phase += self.phase_increment;
+        if self.phase >= 1.0 {
+            self.phase -= self.phase.floor();
+            self.held_value = self.rng.gen_range(-1.0..=1.0);
+        }
+
+        y
+    }
+}
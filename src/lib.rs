@@ -4,34 +4,59 @@ use egui::FontFamily::Proportional;
 use egui::FontId;
 use egui::TextStyle::*;
 
-use std::{sync::{Arc, mpsc::channel}, collections::VecDeque, env};
+use std::{sync::{Arc, mpsc::channel}, env};
 
 use nih_plug::prelude::*;
 use nih_plug_vizia::ViziaState;
 
-use delay::Delay;
-use delay_enums::{DelayTime, DelayTiming};
+use delay::{Delay, MAX_DELAY_MS, MIN_BPM};
+use diffusion::MAX_DIFFUSION_STAGES;
+use delay_enums::{get_beats_per_bar, BeatUnit, DelayMode, DelayTime, DelayTiming, DiffusionPosition, DuckingSource, Interpolation, ReverseFeedback, ShimmerGrain, StereoMode, StopBehaviour, TimeChange};
+use dynamics::{safety_clip, Ducker};
 use egui_editor::*;
 use filter::*;
+use lfo::{Lfo, LfoShape};
+use modulation::TapeModulation;
+use saturation::{Oversampling, SaturationCurve};
+use taps::{pan_gain, SmoothedTap, TapParams, MAX_TAPS};
 mod editor;
 mod delay;
 mod delay_enums;
+mod diffusion;
+mod dynamics;
 mod egui_editor;
-mod filter;
+pub mod filter;
+mod lfo;
+mod modulation;
+mod pitch_shift;
+mod saturation;
+mod taps;
 
 mod egui_my_widgets {
     pub mod my_slider;
 }
 
+// the widest supported layout is 7.1
+const MAX_CHANNELS: usize = 8;
+
 pub struct EffectPlugin {
     params: Arc<PluginParams>,
-    left_delay: Delay,
-    right_delay: Delay,
-    left_feedback_buffer: Box<VecDeque<f32>>,
-    right_feedback_buffer: Box<VecDeque<f32>>,
-    lpf: BiquadFilter,
+    // one delay line per output channel, allocated in `initialize` for the active layout
+    delays: Vec<Delay>,
+    num_input_channels: usize,
     sample_rate: f32,
     bpm: f32,
+    // the ramped tempo starts from the host's tempo after a reset instead of gliding there
+    snap_bpm: bool,
+    was_playing: bool,
+    // modulates the feedback filter
+    lfo: Lfo,
+    // wow/flutter on the read heads
+    tape_modulation: TapeModulation,
+    // shared by all channels so the stereo image doesn't shift while ducking
+    ducker: Ducker,
+    // last seen value of the clear buffer trigger
+    clear_buffer: bool,
 }
 
 #[derive(Params)]
@@ -42,12 +67,60 @@ pub struct PluginParams {
     #[id = "feedback"]
     feedback: FloatParam,
 
+    #[id = "limiter-ceiling"]
+    limiter_ceiling: FloatParam,
+
+    #[id = "delay-mode"]
+    delay_mode: EnumParam<DelayMode>,
+
     #[id = "delay-time"]
     delay_time: EnumParam<DelayTime>,
 
     #[id = "delay-timing"]
     delay_timing: EnumParam<DelayTiming>,
 
+    #[id = "delay-ms"]
+    delay_ms: FloatParam,
+
+    #[id = "link-channels"]
+    link_channels: BoolParam,
+
+    #[id = "right-delay-time"]
+    right_delay_time: EnumParam<DelayTime>,
+
+    #[id = "right-delay-timing"]
+    right_delay_timing: EnumParam<DelayTiming>,
+
+    #[id = "right-delay-ms"]
+    right_delay_ms: FloatParam,
+
+    #[id = "right-offset"]
+    right_offset: FloatParam,
+
+    #[id = "spread"]
+    spread: FloatParam,
+
+    #[id = "surround-offset"]
+    surround_offset: FloatParam,
+
+    #[id = "interpolation"]
+    interpolation: EnumParam<Interpolation>,
+
+    #[id = "multi-tap"]
+    multi_tap: BoolParam,
+
+    #[id = "tap-count"]
+    tap_count: IntParam,
+
+    #[nested(array, group = "Tap")]
+    taps: [TapParams; MAX_TAPS],
+
+    #[id = "time-change"]
+    time_change: EnumParam<TimeChange>,
+
+    #[id = "transition-time"]
+    transition_time: FloatParam,
+
     #[id = "cutoff"]
     cutoff: FloatParam,
 
@@ -57,6 +130,147 @@ pub struct PluginParams {
     #[id = "filter_type"]
     filter_type: EnumParam<FilterType>,
 
+    #[id = "filter-gain"]
+    filter_gain: FloatParam,
+
+    #[id = "filter-engine"]
+    filter_engine: EnumParam<FilterEngine>,
+
+    #[id = "low-cut-enabled"]
+    low_cut_enabled: BoolParam,
+
+    #[id = "low-cut"]
+    low_cut: FloatParam,
+
+    #[id = "low-cut-resonance"]
+    low_cut_resonance: FloatParam,
+
+    #[id = "low-cut-slope"]
+    low_cut_slope: EnumParam<FilterSlope>,
+
+    #[id = "high-cut-enabled"]
+    high_cut_enabled: BoolParam,
+
+    #[id = "high-cut"]
+    high_cut: FloatParam,
+
+    #[id = "high-cut-resonance"]
+    high_cut_resonance: FloatParam,
+
+    #[id = "high-cut-slope"]
+    high_cut_slope: EnumParam<FilterSlope>,
+
+    #[id = "shimmer-semitones"]
+    shimmer_semitones: IntParam,
+
+    #[id = "shimmer-cents"]
+    shimmer_cents: FloatParam,
+
+    #[id = "shimmer-mix"]
+    shimmer_mix: FloatParam,
+
+    #[id = "shimmer-grain"]
+    shimmer_grain: EnumParam<ShimmerGrain>,
+
+    #[id = "diffusion-amount"]
+    diffusion_amount: FloatParam,
+
+    #[id = "diffusion-size"]
+    diffusion_size: FloatParam,
+
+    #[id = "diffusion-stages"]
+    diffusion_stages: IntParam,
+
+    #[id = "diffusion-position"]
+    diffusion_position: EnumParam<DiffusionPosition>,
+
+    #[id = "saturation-curve"]
+    saturation_curve: EnumParam<SaturationCurve>,
+
+    #[id = "drive"]
+    drive: FloatParam,
+
+    #[id = "saturation-mix"]
+    saturation_mix: FloatParam,
+
+    #[id = "oversampling"]
+    oversampling: EnumParam<Oversampling>,
+
+    #[id = "lfo-shape"]
+    lfo_shape: EnumParam<LfoShape>,
+
+    #[id = "lfo-rate"]
+    lfo_rate: EnumParam<DelayTime>,
+
+    #[id = "lfo-timing"]
+    lfo_timing: EnumParam<DelayTiming>,
+
+    #[id = "lfo-cutoff-depth"]
+    lfo_cutoff_depth: FloatParam,
+
+    #[id = "lfo-resonance-depth"]
+    lfo_resonance_depth: FloatParam,
+
+    #[id = "mod-depth"]
+    mod_depth: FloatParam,
+
+    #[id = "mod-rate"]
+    mod_rate: FloatParam,
+
+    #[id = "mod-drift"]
+    mod_drift: FloatParam,
+
+    #[id = "mod-stereo-phase"]
+    mod_stereo_phase: FloatParam,
+
+    #[id = "stereo-mode"]
+    stereo_mode: EnumParam<StereoMode>,
+
+    #[id = "cross-feedback"]
+    cross_feedback: FloatParam,
+
+    #[id = "fallback-bpm"]
+    fallback_bpm: FloatParam,
+
+    #[id = "time-sig-override"]
+    time_sig_override: BoolParam,
+
+    #[id = "time-sig-numerator"]
+    time_sig_numerator: IntParam,
+
+    #[id = "time-sig-denominator"]
+    time_sig_denominator: EnumParam<BeatUnit>,
+
+    #[id = "reverse"]
+    reverse: BoolParam,
+
+    #[id = "reverse-feedback"]
+    reverse_feedback: EnumParam<ReverseFeedback>,
+
+    #[id = "freeze"]
+    freeze: BoolParam,
+
+    #[id = "stop-behaviour"]
+    stop_behaviour: EnumParam<StopBehaviour>,
+
+    #[id = "clear-buffer"]
+    clear_buffer: BoolParam,
+
+    #[id = "ducking-source"]
+    ducking_source: EnumParam<DuckingSource>,
+
+    #[id = "ducking-amount"]
+    ducking_amount: FloatParam,
+
+    #[id = "ducking-threshold"]
+    ducking_threshold: FloatParam,
+
+    #[id = "ducking-attack"]
+    ducking_attack: FloatParam,
+
+    #[id = "ducking-release"]
+    ducking_release: FloatParam,
+
     #[id = "dry"]
     dry: FloatParam,
 
@@ -68,13 +282,16 @@ impl Default for EffectPlugin {
     fn default() -> Self {
         Self {
             params: Arc::new(PluginParams::default()),
-            left_delay: Delay::new(),
-            right_delay: Delay::new(),
-            left_feedback_buffer: Box::new(VecDeque::new()),
-            right_feedback_buffer: Box::new(VecDeque::new()),
+            delays: Vec::new(),
+            num_input_channels: 2,
             sample_rate: 44100.0,
             bpm: 120.0,
-            lpf: BiquadFilter::new(),
+            snap_bpm: true,
+            was_playing: false,
+            lfo: Lfo::new(),
+            tape_modulation: TapeModulation::new(),
+            ducker: Ducker::new(),
+            clear_buffer: false,
         }
     }
 }
@@ -83,14 +300,64 @@ impl Default for PluginParams {
     fn default() -> Self {
         Self {
             editor_state: editor::default_state(),
-            feedback: FloatParam::new("Feedback", 0.625, FloatRange::Linear { min: 0.0, max: 1.0 })
+            feedback: FloatParam::new("Feedback", 0.625, FloatRange::Linear { min: 0.0, max: 1.2 })
             .with_value_to_string(formatters::v2s_f32_percentage(2))
             .with_string_to_value(formatters::s2v_f32_percentage()),
 
+            // above 100% feedback the repeats grow until they reach this level
+            limiter_ceiling: FloatParam::new("Limiter Ceiling", -3.0, FloatRange::Linear { min: -24.0, max: 0.0 })
+            .with_smoother(SmoothingStyle::Linear(20.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            delay_mode: EnumParam::new("Delay Mode", DelayMode::Sync),
+
             delay_time: EnumParam::new("Delay Time", DelayTime::_1_4),
 
             delay_timing: EnumParam::new("Delay Timing", DelayTiming::Straight),
 
+            delay_ms: FloatParam::new("Delay Time (ms)", 250.0, FloatRange::Skewed { min: 1.0, max: MAX_DELAY_MS, factor: 0.3 })
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            link_channels: BoolParam::new("Link Channels", true),
+
+            right_delay_time: EnumParam::new("Right Delay Time", DelayTime::_1_4),
+
+            right_delay_timing: EnumParam::new("Right Delay Timing", DelayTiming::Straight),
+
+            right_delay_ms: FloatParam::new("Right Delay Time (ms)", 250.0, FloatRange::Skewed { min: 1.0, max: MAX_DELAY_MS, factor: 0.3 })
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            right_offset: FloatParam::new("Right Offset", 0.0, FloatRange::Linear { min: -50.0, max: 50.0 })
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            // shortens the left delay and lengthens the right one by the same percentage
+            spread: FloatParam::new("Spread", 0.0, FloatRange::Linear { min: -0.5, max: 0.5 })
+            .with_value_to_string(formatters::v2s_f32_percentage(1))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            // every channel after the front pair is delayed by this much more than the previous one
+            surround_offset: FloatParam::new("Surround Offset", 0.0, FloatRange::Linear { min: -50.0, max: 50.0 })
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            interpolation: EnumParam::new("Interpolation", Interpolation::Linear),
+
+            multi_tap: BoolParam::new("Multi-Tap", false),
+
+            tap_count: IntParam::new("Tap Count", 4, IntRange::Linear { min: 1, max: MAX_TAPS as i32 }),
+
+            taps: std::array::from_fn(TapParams::new),
+
+            time_change: EnumParam::new("Time Change", TimeChange::Jump),
+
+            transition_time: FloatParam::new("Transition Time", 100.0, FloatRange::Skewed { min: 1.0, max: 2000.0, factor: 0.3 })
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
             cutoff: FloatParam::new("Cutoff", 15000.0, FloatRange::Skewed { min: 20.0, max: 20000.0, factor: 0.2 })
             .with_unit("")
             .with_value_to_string(formatters::v2s_f32_hz_then_khz(2))
@@ -101,6 +368,178 @@ impl Default for PluginParams {
 
             filter_type: EnumParam::new("Filter Type", FilterType::LowPass2),
 
+            // only used by the shelf and peak filters
+            filter_gain: FloatParam::new("Filter Gain", 0.0, FloatRange::Linear { min: -24.0, max: 24.0 })
+            .with_smoother(SmoothingStyle::Linear(20.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            // the state variable filter stays clean under fast cutoff modulation, the biquad is
+            // the original sound
+            filter_engine: EnumParam::new("Filter Engine", FilterEngine::Biquad),
+
+            // the low and high cut sit in series with the main filter, so both ends of the
+            // repeats can be thinned out at once
+            low_cut_enabled: BoolParam::new("Low Cut", false),
+
+            low_cut: FloatParam::new("Low Cut Frequency", 150.0, FloatRange::Skewed { min: 20.0, max: 20000.0, factor: 0.2 })
+            .with_smoother(SmoothingStyle::Logarithmic(20.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(2))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+
+            low_cut_resonance: FloatParam::new("Low Cut Resonance", 0.707, FloatRange::Linear { min: 0.5, max: 2.0 })
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            low_cut_slope: EnumParam::new("Low Cut Slope", FilterSlope::Db12),
+
+            high_cut_enabled: BoolParam::new("High Cut", false),
+
+            high_cut: FloatParam::new("High Cut Frequency", 6000.0, FloatRange::Skewed { min: 20.0, max: 20000.0, factor: 0.2 })
+            .with_smoother(SmoothingStyle::Logarithmic(20.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(2))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+
+            high_cut_resonance: FloatParam::new("High Cut Resonance", 0.707, FloatRange::Linear { min: 0.5, max: 2.0 })
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            high_cut_slope: EnumParam::new("High Cut Slope", FilterSlope::Db12),
+
+            shimmer_semitones: IntParam::new("Shimmer Pitch", 12, IntRange::Linear { min: -24, max: 24 })
+            .with_unit(" st"),
+
+            shimmer_cents: FloatParam::new("Shimmer Fine", 0.0, FloatRange::Linear { min: -100.0, max: 100.0 })
+            .with_unit(" ct")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            // 0% leaves the repeats at their original pitch
+            shimmer_mix: FloatParam::new("Shimmer Mix", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+            .with_smoother(SmoothingStyle::Linear(20.0))
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            // grains follow the tempo
+            shimmer_grain: EnumParam::new("Shimmer Grain", ShimmerGrain::_1_32),
+
+            // 0% keeps the repeats discrete
+            diffusion_amount: FloatParam::new("Diffusion", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+            .with_smoother(SmoothingStyle::Linear(20.0))
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            // the filters are redesigned whenever this changes, so it isn't smoothed
+            diffusion_size: FloatParam::new("Diffusion Size", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 })
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            diffusion_stages: IntParam::new("Diffusion Stages", 4, IntRange::Linear { min: 1, max: MAX_DIFFUSION_STAGES as i32 }),
+
+            diffusion_position: EnumParam::new("Diffusion Position", DiffusionPosition::Feedback),
+
+            saturation_curve: EnumParam::new("Saturation", SaturationCurve::SoftClip),
+
+            drive: FloatParam::new("Drive", 0.0, FloatRange::Linear { min: 0.0, max: 24.0 })
+            .with_smoother(SmoothingStyle::Linear(20.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            // 0% takes the saturation out of the loop completely
+            saturation_mix: FloatParam::new("Saturation Mix", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+            .with_smoother(SmoothingStyle::Linear(20.0))
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            oversampling: EnumParam::new("Oversampling", Oversampling::X2),
+
+            lfo_shape: EnumParam::new("LFO Shape", LfoShape::Sine),
+
+            // one LFO cycle lasts this long
+            lfo_rate: EnumParam::new("LFO Rate", DelayTime::_1),
+
+            lfo_timing: EnumParam::new("LFO Timing", DelayTiming::Straight),
+
+            lfo_cutoff_depth: FloatParam::new("LFO Cutoff Depth", 0.0, FloatRange::Linear { min: 0.0, max: 4.0 })
+            .with_unit(" oct")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            lfo_resonance_depth: FloatParam::new("LFO Resonance Depth", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            mod_depth: FloatParam::new("Wow/Flutter Depth", 0.0, FloatRange::Skewed { min: 0.0, max: 10.0, factor: 0.5 })
+            .with_smoother(SmoothingStyle::Linear(20.0))
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            mod_rate: FloatParam::new("Wow/Flutter Rate", 0.5, FloatRange::Skewed { min: 0.05, max: 20.0, factor: 0.3 })
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // blends the periodic wobble into random drift
+            mod_drift: FloatParam::new("Wow/Flutter Drift", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            // phase shift between neighbouring channels, 180 degrees puts left and right in opposition
+            mod_stereo_phase: FloatParam::new("Wow/Flutter Stereo Phase", 90.0, FloatRange::Linear { min: 0.0, max: 180.0 })
+            .with_unit("°")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            stereo_mode: EnumParam::new("Stereo Mode", StereoMode::Stereo),
+
+            cross_feedback: FloatParam::new("Cross Feedback", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 })
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            // used whenever the host does not report a tempo
+            fallback_bpm: FloatParam::new("Fallback BPM", 120.0, FloatRange::Linear { min: MIN_BPM, max: 300.0 })
+            .with_unit(" BPM")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            // the time signature below is used when the host doesn't report one, or always when
+            // the override is enabled
+            time_sig_override: BoolParam::new("Override Time Signature", false),
+
+            time_sig_numerator: IntParam::new("Time Signature Numerator", 4, IntRange::Linear { min: 1, max: 16 }),
+
+            time_sig_denominator: EnumParam::new("Time Signature Denominator", BeatUnit::Quarter),
+
+            // plays every chunk of the delay time backwards. only the main read head can be
+            // reversed, so this has no effect while multi-tap is enabled.
+            reverse: BoolParam::new("Reverse", false),
+
+            reverse_feedback: EnumParam::new("Reverse Feedback", ReverseFeedback::Reversed),
+
+            // stops taking in new input and loops the buffer as it is, without any loss
+            freeze: BoolParam::new("Freeze", false),
+
+            stop_behaviour: EnumParam::new("On Transport Stop", StopBehaviour::Ring),
+
+            // toggling this in either direction kills the tails, so it works both as a button in
+            // the GUI and as a trigger from automation
+            clear_buffer: BoolParam::new("Clear Buffer", false),
+
+            // the sidechain is only available in mono and stereo. an unconnected sidechain is
+            // silent and leaves the wet signal alone, the surround layouts always duck from the
+            // input.
+            ducking_source: EnumParam::new("Ducking Source", DuckingSource::Input),
+
+            ducking_amount: FloatParam::new("Ducking Amount", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+            .with_smoother(SmoothingStyle::Linear(20.0))
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            ducking_threshold: FloatParam::new("Ducking Threshold", -24.0, FloatRange::Linear { min: -60.0, max: 0.0 })
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            ducking_attack: FloatParam::new("Ducking Attack", 10.0, FloatRange::Skewed { min: 0.1, max: 200.0, factor: 0.3 })
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            ducking_release: FloatParam::new("Ducking Release", 250.0, FloatRange::Skewed { min: 10.0, max: 2000.0, factor: 0.3 })
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
             dry: FloatParam::new("Dry", 1.0, FloatRange::Linear { min: 0.0, max: 1.0 })
             .with_value_to_string(formatters::v2s_f32_percentage(2))
             .with_string_to_value(formatters::s2v_f32_percentage()),
@@ -125,6 +564,49 @@ impl Plugin for EffectPlugin {
         AudioIOLayout {
             main_input_channels: NonZeroU32::new(2),
             main_output_channels: NonZeroU32::new(2),
+            aux_input_ports: &[new_nonzero_u32(2)],
+            names: PortNames {
+                aux_inputs: &["Sidechain"],
+                ..PortNames::const_default()
+            },
+            ..AudioIOLayout::const_default()
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(1),
+            main_output_channels: NonZeroU32::new(1),
+            aux_input_ports: &[new_nonzero_u32(1)],
+            names: PortNames {
+                aux_inputs: &["Sidechain"],
+                ..PortNames::const_default()
+            },
+            ..AudioIOLayout::const_default()
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(1),
+            main_output_channels: NonZeroU32::new(2),
+            aux_input_ports: &[new_nonzero_u32(1)],
+            names: PortNames {
+                aux_inputs: &["Sidechain"],
+                ..PortNames::const_default()
+            },
+            ..AudioIOLayout::const_default()
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(6),
+            main_output_channels: NonZeroU32::new(6),
+            names: PortNames {
+                layout: Some("5.1"),
+                ..PortNames::const_default()
+            },
+            ..AudioIOLayout::const_default()
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(8),
+            main_output_channels: NonZeroU32::new(8),
+            names: PortNames {
+                layout: Some("7.1"),
+                ..PortNames::const_default()
+            },
             ..AudioIOLayout::const_default()
         },
     ];
@@ -158,78 +640,312 @@ impl Plugin for EffectPlugin {
         // function if you do not need it.
         self.sample_rate = _buffer_config.sample_rate;
 
-        self.left_delay.resize_buffers(self.sample_rate, 120.0);
-        self.right_delay.resize_buffers(self.sample_rate, 120.0);
-        self.lpf.set_sample_rate(self.sample_rate);
-        self.lpf.second_order_lpf_coefficients(self.sample_rate, 20000.0, 0.707);
-        //self.left_feedback_buffer = Box::new(VecDeque::new());
+        // everything the audio thread needs is allocated here, tempo changes only move the
+        // read heads
+        let num_output_channels = _audio_io_layout.main_output_channels.map_or(0, NonZeroU32::get) as usize;
+        self.num_input_channels = _audio_io_layout.main_input_channels.map_or(0, NonZeroU32::get) as usize;
+        self.delays = (0..num_output_channels.min(MAX_CHANNELS))
+            .map(|_| {
+                let mut delay = Delay::new();
+                delay.resize_buffers(self.sample_rate);
+                delay
+            })
+            .collect();
+        self.clear_buffer = self.params.clear_buffer.value();
         true
     }
 
     fn reset(&mut self) {
         // Reset buffers and envelopes here. This can be called from the audio thread and may not
         // allocate. You can remove this function if you do not need it.
+        for delay in self.delays.iter_mut() {
+            delay.reset();
+        }
+        self.was_playing = false;
+        self.snap_bpm = true;
+        self.lfo.reset();
+        self.tape_modulation.reset();
+        self.ducker.reset();
     }
 
     fn process(
         &mut self,
         buffer: &mut Buffer,
-        _aux: &mut AuxiliaryBuffers,
+        aux: &mut AuxiliaryBuffers,
         _context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
         // In current configuration this function iterates as follows:
         // 1. outer loop iterates block-size times
         // 2. inner loop iterates channel-size times. 
-        let bpm = _context.transport().tempo.unwrap();
-        if self.bpm != bpm as f32 {
-            self.left_feedback_buffer = Box::new(VecDeque::with_capacity(((DelayTime::get_max_in_beats() * 1.5 * 60.0 * self.sample_rate) / bpm as f32) as usize));
-            self.right_feedback_buffer = Box::new(VecDeque::with_capacity(((DelayTime::get_max_in_beats() * 1.5 * 60.0 * self.sample_rate) / bpm as f32) as usize));
-            self.bpm = bpm as f32;
-            for i in 0..self.left_feedback_buffer.capacity() {
-                self.left_feedback_buffer.push_back(0.0);
-                self.right_feedback_buffer.push_back(0.0);
+        let transport = _context.transport();
+        let bpm = transport
+            .tempo
+            .map_or(self.params.fallback_bpm.value(), |tempo| tempo as f32);
+        let (numerator, denominator) = match (transport.time_sig_numerator, transport.time_sig_denominator) {
+            (Some(numerator), Some(denominator)) if !self.params.time_sig_override.value() && numerator > 0 && denominator > 0 => {
+                (numerator, denominator)
+            },
+            _ => (
+                self.params.time_sig_numerator.value(),
+                self.params.time_sig_denominator.value().get_denominator(),
+            ),
+        };
+        let beats_per_bar = get_beats_per_bar(numerator, denominator);
+        if self.snap_bpm {
+            self.snap_bpm = false;
+            self.bpm = bpm;
+        }
+        // tempo changes get spread over the block so ramps are followed without jumps
+        let bpm_step = (bpm - self.bpm) / buffer.samples().max(1) as f32;
+
+        let clear_buffer = self.params.clear_buffer.value();
+        if clear_buffer != self.clear_buffer {
+            self.clear_buffer = clear_buffer;
+            for delay in self.delays.iter_mut() {
+                delay.reset();
             }
         }
 
-        for (i, channel_samples) in buffer.iter_samples().enumerate() {
+        let playing = transport.playing;
+        let stop_behaviour = self.params.stop_behaviour.value();
+        if self.was_playing && !playing && stop_behaviour == StopBehaviour::Clear {
+            for delay in self.delays.iter_mut() {
+                delay.reset();
+            }
+        }
+        if playing && !self.was_playing {
+            self.lfo.reset();
+        }
+        self.was_playing = playing;
+        let frozen = self.params.freeze.value() || (!playing && stop_behaviour == StopBehaviour::Freeze);
+
+        let num_taps = if self.params.multi_tap.value() {
+            self.params.tap_count.value() as usize
+        } else {
+            0
+        };
+        let num_delays = self.delays.len();
+
+        self.ducker.set_params(
+            self.params.ducking_threshold.value(),
+            self.params.ducking_attack.value(),
+            self.params.ducking_release.value(),
+            self.sample_rate,
+        );
+        // hosts always provide the sidechain buffer on layouts that have the port, filled with
+        // silence when nothing is routed to it, which means no ducking at all. the surround
+        // layouts don't have the port, so they fall back to the input.
+        let sidechain = match self.params.ducking_source.value() {
+            DuckingSource::Sidechain => aux.inputs.first().map(|sidechain| sidechain.as_slice_immutable()),
+            DuckingSource::Input => None,
+        };
+
+        for (i, mut channel_samples) in buffer.iter_samples().enumerate() {
             // Smoothing is optionally built into the parameters themselves
+            self.bpm += bpm_step;
 
             let feedback = self.params.feedback.smoothed.next();
+            let limiter_ceiling = self.params.limiter_ceiling.smoothed.next();
+            let delay_mode = self.params.delay_mode.value();
+            let delay_ms = self.params.delay_ms.smoothed.next();
             let delay_time = self.params.delay_time.value();
             let delay_timing = self.params.delay_timing.value();
+            let (right_delay_time, right_delay_timing, right_delay_ms) = if self.params.link_channels.value() {
+                (delay_time, delay_timing, delay_ms)
+            } else {
+                (
+                    self.params.right_delay_time.value(),
+                    self.params.right_delay_timing.value(),
+                    self.params.right_delay_ms.smoothed.next(),
+                )
+            };
+            let right_offset = self.params.right_offset.smoothed.next();
+            let spread = self.params.spread.smoothed.next();
+            let surround_offset = self.params.surround_offset.smoothed.next();
+            let interpolation = self.params.interpolation.value();
+            let time_change = self.params.time_change.value();
+            let transition_time = self.params.transition_time.smoothed.next();
             let cutoff = self.params.cutoff.smoothed.next();
             let resonance = self.params.resonance.smoothed.next();
+            let lfo_cutoff_depth = self.params.lfo_cutoff_depth.smoothed.next();
+            let lfo_resonance_depth = self.params.lfo_resonance_depth.smoothed.next();
+            let mod_depth = self.params.mod_depth.smoothed.next();
+            let mod_rate = self.params.mod_rate.smoothed.next();
+            let mod_drift = self.params.mod_drift.smoothed.next();
+            let mod_stereo_phase = self.params.mod_stereo_phase.smoothed.next();
             let filter_type = self.params.filter_type.value();
+            let filter_gain = self.params.filter_gain.smoothed.next();
+            let filter_engine = self.params.filter_engine.value();
+            let max_cutoff = 20000.0_f32.min(0.49 * self.sample_rate);
+            let low_cut_enabled = self.params.low_cut_enabled.value();
+            let low_cut = self.params.low_cut.smoothed.next().min(max_cutoff);
+            let low_cut_resonance = self.params.low_cut_resonance.value();
+            let low_cut_slope = self.params.low_cut_slope.value();
+            let high_cut_enabled = self.params.high_cut_enabled.value();
+            let high_cut = self.params.high_cut.smoothed.next().min(max_cutoff);
+            let high_cut_resonance = self.params.high_cut_resonance.value();
+            let high_cut_slope = self.params.high_cut_slope.value();
+            let shimmer_pitch = self.params.shimmer_semitones.value() as f32 + self.params.shimmer_cents.value() / 100.0;
+            let shimmer_mix = self.params.shimmer_mix.smoothed.next();
+            let shimmer_grain_ms = self.params.shimmer_grain.value().get_grain_in_beats() * 60000.0 / self.bpm;
+            let diffusion_amount = self.params.diffusion_amount.smoothed.next();
+            let diffusion_size = self.params.diffusion_size.value();
+            let diffusion_stages = self.params.diffusion_stages.value() as usize;
+            let diffusion_position = self.params.diffusion_position.value();
+            let saturation_curve = self.params.saturation_curve.value();
+            let drive = self.params.drive.smoothed.next();
+            let saturation_mix = self.params.saturation_mix.smoothed.next();
+            let oversampling = self.params.oversampling.value();
+            let stereo_mode = self.params.stereo_mode.value();
+            let cross_feedback = self.params.cross_feedback.smoothed.next();
             let dry = self.params.dry.smoothed.next();
             let wet = self.params.wet.smoothed.next();
+            let ducking_amount = self.params.ducking_amount.smoothed.next();
+            let reverse = self.params.reverse.value();
+            let reverse_feedback = self.params.reverse_feedback.value();
+            let mut taps = [SmoothedTap::default(); MAX_TAPS];
+            for (tap, tap_params) in taps.iter_mut().zip(self.params.taps.iter()) {
+                *tap = tap_params.next_smoothed();
+            }
 
-            self.left_delay.set_delay(delay_time, delay_timing, self.sample_rate, self.bpm);
-            self.right_delay.set_delay(delay_time, delay_timing, self.sample_rate, self.bpm);
-            match filter_type {
-                FilterType::LowPass2 => self.lpf.second_order_lpf_coefficients(self.sample_rate, cutoff, resonance),
-                FilterType::HighPass2 => self.lpf.second_order_hpf_coefficients(self.sample_rate, cutoff, resonance),
-                FilterType::BandPass => self.lpf.band_pass_coefficients(self.sample_rate, cutoff, resonance),
+            let lfo_period = self.params.lfo_rate.value().get_delay_time_in_beats(beats_per_bar)
+                * self.params.lfo_timing.value().get_multiplier()
+                * 60.0
+                * self.sample_rate
+                / self.bpm;
+            self.lfo.set_shape(self.params.lfo_shape.value());
+            self.lfo.set_period(lfo_period);
+            let lfo_value = self.lfo.next();
+            // the cutoff is swept in octaves, the resonance across its parameter range
+            let cutoff = (cutoff * 2.0_f32.powf(lfo_value * lfo_cutoff_depth)).clamp(20.0, 20000.0_f32.min(0.49 * self.sample_rate));
+            let resonance = (resonance + lfo_value * lfo_resonance_depth * 1.5).clamp(0.5, 2.0);
+
+            self.tape_modulation.set_params(mod_rate, mod_depth, mod_drift, mod_stereo_phase / 360.0, self.sample_rate);
+            self.tape_modulation.next();
+
+            let num_channels = channel_samples.len().min(self.delays.len());
+            for (num, delay) in self.delays.iter_mut().enumerate() {
+                // the front pair gets the left/right time controls, any further channels follow
+                // the left channel shifted by a growing offset
+                let (time_scale, time_offset) = match num {
+                    0 => (1.0 - spread, 0.0),
+                    1 => (1.0 + spread, right_offset),
+                    _ => (1.0, surround_offset * (num - 1) as f32),
+                };
+                let (channel_delay_time, channel_delay_timing, channel_delay_ms) = if num == 1 {
+                    (right_delay_time, right_delay_timing, right_delay_ms)
+                } else {
+                    (delay_time, delay_timing, delay_ms)
+                };
+
+                delay.set_interpolation(interpolation);
+                delay.set_time_change(time_change, transition_time);
+                delay.set_time_offset(time_scale, time_offset);
+                delay.set_modulation(self.tape_modulation.get_offset(num));
+                match delay_mode {
+                    DelayMode::Sync => delay.set_delay(channel_delay_time, channel_delay_timing, self.bpm, beats_per_bar),
+                    DelayMode::Free => delay.set_delay_ms(channel_delay_ms),
+                }
+                delay.set_feedback(feedback);
+                delay.set_limiter_ceiling(limiter_ceiling);
+                delay.set_freeze(frozen);
+                // the taps replace the main read head and always play forward
+                delay.set_reverse(reverse && num_taps == 0, reverse_feedback);
+                delay.set_shimmer(shimmer_pitch, shimmer_mix, shimmer_grain_ms);
+                delay.set_diffusion(diffusion_amount, diffusion_size, diffusion_stages, diffusion_position);
+                delay.set_saturation(saturation_curve, drive, saturation_mix, oversampling);
+                delay.set_filter(filter_engine, filter_type, cutoff, resonance, filter_gain);
+                delay.set_low_cut(low_cut_enabled, low_cut, low_cut_resonance, low_cut_slope);
+                delay.set_high_cut(high_cut_enabled, high_cut, high_cut_resonance, high_cut_slope);
+                for (index, (tap_params, tap)) in self.params.taps.iter().zip(taps.iter()).enumerate().take(num_taps) {
+                    match tap_params.delay_mode.value() {
+                        DelayMode::Sync => delay.set_tap_delay(index, tap_params.delay_time.value(), tap_params.delay_timing.value(), self.bpm, beats_per_bar),
+                        DelayMode::Free => delay.set_tap_delay_ms(index, tap.delay_ms),
+                    }
+                    let muted = tap_params.mute.value();
+                    let gain = if muted { 0.0 } else { tap.gain };
+                    delay.set_tap(index, gain, pan_gain(tap.pan, num, num_delays), !muted && tap_params.feedback_source.value());
+                    delay.set_tap_filter(index, tap_params.filter_type.value(), tap.cutoff, tap.resonance);
+                }
+                delay.set_num_taps(num_taps);
             }
-            
-            for (num, sample) in channel_samples.into_iter().enumerate() {
-                // processing
-                if num == 0 {
-                    let x = self.lpf.process_left(*sample + feedback * self.left_feedback_buffer.get(0).unwrap());
-                    let delayed_signal = self.left_delay.process(x);
-                    *sample = dry * *sample + wet * delayed_signal;
-                    self.left_feedback_buffer.rotate_right(1);
-                    self.left_feedback_buffer[0] = delayed_signal;
+
+            // output channels without a matching input channel (e.g. mono to stereo) get fed
+            // with the input channels in turn
+            let mut input = [0.0; MAX_CHANNELS];
+            for num in 0..num_channels {
+                input[num] = if num < self.num_input_channels {
+                    *channel_samples.get_mut(num).unwrap()
                 } else {
-                    let x = self.lpf.process_right(*sample + feedback * self.right_feedback_buffer.get(0).unwrap());
-                    let delayed_signal = self.right_delay.process(x);
-                    *sample = dry * *sample + wet * delayed_signal;
-                    self.right_feedback_buffer.rotate_right(1);
-                    self.right_feedback_buffer[0] = delayed_signal;
+                    input[num % self.num_input_channels.max(1)]
+                };
+            }
+
+            let key = match sidechain {
+                Some(sidechain) => sidechain.iter().fold(0.0_f32, |key, channel| key.max(channel[i].abs())),
+                None => input[..num_channels].iter().fold(0.0_f32, |key, x| key.max(x.abs())),
+            };
+            let ducking_gain = self.ducker.process(key, ducking_amount);
+
+            // all delays are read before any is written, so the feedback can cross over
+            let mut output = [0.0; MAX_CHANNELS];
+            let mut feedback_signal = [0.0; MAX_CHANNELS];
+            for num in 0..num_channels {
+                (output[num], feedback_signal[num]) = self.delays[num].read();
+            }
+
+            // the stereo modes only apply to the front pair, surround channels run independently
+            let first_independent_channel = if num_channels >= 2 {
+                let (left_in, right_in) = (input[0], input[1]);
+                let (left_feedback, right_feedback) = (feedback_signal[0], feedback_signal[1]);
+                let mono_in = 0.5 * (left_in + right_in);
+                match stereo_mode {
+                    StereoMode::Stereo => {
+                        self.delays[0].write(left_in, left_feedback);
+                        self.delays[1].write(right_in, right_feedback);
+                    },
+                    StereoMode::PingPong => {
+                        // the input only enters on the left, every repeat then swaps sides
+                        self.delays[0].write(mono_in, right_feedback);
+                        self.delays[1].write(0.0, left_feedback);
+                    },
+                    StereoMode::CrossFeedback => {
+                        let left_mixed = (1.0 - cross_feedback) * left_feedback + cross_feedback * right_feedback;
+                        let right_mixed = (1.0 - cross_feedback) * right_feedback + cross_feedback * left_feedback;
+                        self.delays[0].write(left_in, left_mixed);
+                        self.delays[1].write(right_in, right_mixed);
+                    },
+                    StereoMode::Mono => {
+                        self.delays[0].write(mono_in, left_feedback);
+                        self.delays[1].write(mono_in, right_feedback);
+                        output[1] = output[0];
+                    },
                 }
+                2
+            } else {
+                0
+            };
+            for num in first_independent_channel..num_channels {
+                self.delays[num].write(input[num], feedback_signal[num]);
+            }
+
+            for (num, sample) in channel_samples.into_iter().enumerate().take(num_channels) {
+                // processing
+                // only the wet signal is protected, the dry signal passes through untouched
+                *sample = dry * input[num] + safety_clip(wet * ducking_gain * output[num]);
             }
         }
 
-        ProcessStatus::Normal
+        // report how long the repeats keep going once the input goes silent, so offline renders
+        // don't cut them off
+        let mut tail_samples: f32 = 0.0;
+        for delay in self.delays.iter() {
+            match delay.get_tail_samples() {
+                Some(samples) => tail_samples = tail_samples.max(samples),
+                None => return ProcessStatus::KeepAlive,
+            }
+        }
+        ProcessStatus::Tail(tail_samples.min(u32::MAX as f32) as u32)
     }
 
     fn editor(&mut self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
@@ -247,7 +963,7 @@ impl ClapPlugin for EffectPlugin {
     const CLAP_SUPPORT_URL: Option<&'static str> = None;
 
     // Don't forget to change these features
-    const CLAP_FEATURES: &'static [ClapFeature] = &[ClapFeature::AudioEffect, ClapFeature::Stereo];
+    const CLAP_FEATURES: &'static [ClapFeature] = &[ClapFeature::AudioEffect, ClapFeature::Stereo, ClapFeature::Mono, ClapFeature::Surround];
 }
 
 impl Vst3Plugin for EffectPlugin {
@@ -260,3 +976,20 @@ impl Vst3Plugin for EffectPlugin {
 
 
 nih_export_vst3!(EffectPlugin);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_mono_and_stereo_have_a_sidechain() {
+        for layout in EffectPlugin::AUDIO_IO_LAYOUTS {
+            assert_eq!(layout.aux_input_ports.len(), layout.names.aux_inputs.len());
+            let num_outputs = layout.main_output_channels.map_or(0, NonZeroU32::get);
+            assert_eq!(layout.aux_input_ports.is_empty(), num_outputs > 2);
+            for port in layout.aux_input_ports {
+                assert_eq!(Some(*port), layout.main_input_channels);
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::f32::consts::PI;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::MAX_CHANNELS;
+
+// wow/flutter for the read heads: a sine shared by all channels, shifted in phase per channel,
+// blended with a slowly drifting random value that's independent for every channel
+pub struct TapeModulation {
+    // position within the current cycle, 0..1
+    phase: f32,
+    phase_increment: f32,
+    // phase shift between neighbouring channels, in cycles
+    stereo_phase: f32,
+    depth_samples: f32,
+    // how much of the modulation is random drift instead of the sine, 0..1
+    drift_amount: f32,
+    drift: [f32; MAX_CHANNELS],
+    drift_target: [f32; MAX_CHANNELS],
+    drift_coeff: f32,
+    rng: StdRng,
+}
+
+impl TapeModulation {
+    pub fn new() -> Self {
+        TapeModulation {
+            phase: 0.0,
+            phase_increment: 0.0,
+            stereo_phase: 0.0,
+            depth_samples: 0.0,
+            drift_amount: 0.0,
+            drift: [0.0; MAX_CHANNELS],
+            drift_target: [0.0; MAX_CHANNELS],
+            drift_coeff: 0.0,
+            rng: StdRng::seed_from_u64(0x776f_7721),
+        }
+    }
+
+    pub fn set_params(&mut self, rate_hz: f32, depth_ms: f32, drift_amount: f32, stereo_phase: f32, sample_rate: f32) {
+        self.phase_increment = rate_hz / sample_rate;
+        self.depth_samples = depth_ms / 1000.0 * sample_rate;
+        self.drift_amount = drift_amount;
+        self.stereo_phase = stereo_phase;
+        // the drift moves towards a new random target every cycle, settling within a quarter of it
+        let period_samples = sample_rate / rate_hz.max(0.01);
+        self.drift_coeff = 1.0 - (-4.0 / period_samples).exp();
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+        self.drift = [0.0; MAX_CHANNELS];
+        self.drift_target = [0.0; MAX_CHANNELS];
+    }
+
+    // moves everything along by one sample
+    pub fn next(&mut self) {
+        self.phase += self.phase_increment;
+        if self.phase >= 1.0 {
+            self.phase -= self.phase.floor();
+            for target in self.drift_target.iter_mut() {
+                *target = self.rng.gen_range(-1.0..=1.0);
+            }
+        }
+        for (drift, target) in self.drift.iter_mut().zip(self.drift_target.iter()) {
+            *drift += (target - *drift) * self.drift_coeff;
+        }
+    }
+
+    // the current read position offset for the given channel, in samples
+    pub fn get_offset(&self, channel: usize) -> f32 {
+        let sine = (2.0 * PI * (self.phase + channel as f32 * self.stereo_phase)).sin();
+        let drift = self.drift[channel % MAX_CHANNELS];
+        self.depth_samples * ((1.0 - self.drift_amount) * sine + self.drift_amount * drift)
+    }
+}
@@ -0,0 +1,83 @@
+use std::f32::consts::PI;
+
+// longest grain the shifter's buffer is allocated for, a sixteenth note at MIN_BPM
+pub const MAX_GRAIN_MS: f32 = 375.0;
+// keeps the read heads clear of the write head
+const MIN_DISTANCE: f32 = 2.0;
+
+// dual read head pitch shifter for the feedback path. both heads drift through the last grain at
+// the pitch ratio's speed, half a grain apart, and their sin² windows add up to one, so one head
+// always fades out while it jumps back.
+pub struct PitchShifter {
+    buffer: Vec<f32>,
+    current_sample: usize,
+    // position within the current grain, 0..1
+    phase: f32,
+    ratio: f32,
+    grain_samples: f32,
+    mix: f32,
+    sample_rate: f32,
+}
+
+impl PitchShifter {
+    pub fn new() -> Self {
+        PitchShifter {
+            buffer: vec![0.0; 8],
+            current_sample: 0,
+            phase: 0.0,
+            ratio: 1.0,
+            grain_samples: MIN_DISTANCE,
+            mix: 0.0,
+            sample_rate: 44100.0,
+        }
+    }
+
+    pub fn resize_buffers(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.buffer = vec![0.0; (MAX_GRAIN_MS / 1000.0 * sample_rate) as usize + 2 * MIN_DISTANCE as usize + 2];
+        self.current_sample = 0;
+    }
+
+    pub fn set_params(&mut self, semitones: f32, mix: f32, grain_ms: f32) {
+        self.ratio = 2.0_f32.powf(semitones / 12.0);
+        self.mix = mix;
+        let max_grain = self.buffer.len() as f32 - 2.0 * MIN_DISTANCE - 2.0;
+        self.grain_samples = (grain_ms / 1000.0 * self.sample_rate).clamp(MIN_DISTANCE, max_grain.max(MIN_DISTANCE));
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.phase = 0.0;
+    }
+
+    fn read_at(&self, distance: f32) -> f32 {
+        let len = self.buffer.len();
+        let whole = distance.floor();
+        let frac = distance - whole;
+        let index = (self.current_sample + len - whole as usize % len) % len;
+        let x0 = self.buffer[index];
+        let x1 = self.buffer[(index + len - 1) % len];
+        x0 + frac * (x1 - x0)
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        self.buffer[self.current_sample] = x;
+
+        let y = if self.mix > 0.0 {
+            let mut shifted = 0.0;
+            for head in 0..2 {
+                let phase = (self.phase + 0.5 * head as f32).fract();
+                let window = (PI * phase).sin().powi(2);
+                shifted += window * self.read_at(MIN_DISTANCE + phase * self.grain_samples);
+            }
+            (1.0 - self.mix) * x + self.mix * shifted
+        } else {
+            x
+        };
+
+        // a head moving faster than the write head gets closer to it, which raises the pitch
+        self.phase = (self.phase + (1.0 - self.ratio) / self.grain_samples).rem_euclid(1.0);
+        self.current_sample = (self.current_sample + 1) % self.buffer.len();
+        y
+    }
+}
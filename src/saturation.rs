@@ -0,0 +1,204 @@
+use nih_plug::prelude::Enum;
+
+use crate::filter::BiquadFilter;
+
+// bias of the tube curve, shifts the curve so positive and negative halves clip differently
+const TUBE_BIAS: f32 = 0.3;
+// Q values of a 4th order Butterworth lowpass built from two biquads
+const BUTTERWORTH_Q: [f32; 2] = [0.541, 1.307];
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum SaturationCurve {
+    SoftClip,
+    Tape,
+    Tube,
+    HardClip,
+}
+
+impl Enum for SaturationCurve {
+    fn variants() -> &'static [&'static str] {
+        &["Soft Clip", "Tape", "Tube", "Hard Clip"]
+    }
+
+    fn ids() -> Option<&'static [&'static str]> {
+        Some(&["soft-clip", "tape", "tube", "hard-clip"])
+    }
+
+    fn to_index(self) -> usize {
+        match self {
+            SaturationCurve::SoftClip => 0,
+            SaturationCurve::Tape => 1,
+            SaturationCurve::Tube => 2,
+            SaturationCurve::HardClip => 3,
+        }
+    }
+
+    fn from_index(index: usize) -> Self {
+        match index {
+            0 => SaturationCurve::SoftClip,
+            1 => SaturationCurve::Tape,
+            2 => SaturationCurve::Tube,
+            3 => SaturationCurve::HardClip,
+            _ => SaturationCurve::SoftClip,
+        }
+    }
+}
+
+impl SaturationCurve {
+    // every curve has unity gain around zero and stays within -1..1
+    pub fn apply(&self, x: f32) -> f32 {
+        let y = match self {
+            SaturationCurve::SoftClip => x.tanh(),
+            SaturationCurve::Tape => {
+                // cubic soft clipper, rounder than tanh and flat from +-1.5 onwards
+                let x = x.clamp(-1.5, 1.5);
+                x - 4.0 / 27.0 * x * x * x
+            },
+            SaturationCurve::Tube => {
+                // the shifted tanh adds even harmonics, the offset keeps silence at zero
+                let y = (x + TUBE_BIAS).tanh() - TUBE_BIAS.tanh();
+                y / (1.0 - TUBE_BIAS.tanh() * TUBE_BIAS.tanh())
+            },
+            SaturationCurve::HardClip => x.clamp(-1.0, 1.0),
+        };
+        y.clamp(-1.0, 1.0)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Oversampling {
+    Off,
+    X2,
+    X4,
+}
+
+impl Enum for Oversampling {
+    fn variants() -> &'static [&'static str] {
+        &["Off", "2x", "4x"]
+    }
+
+    fn ids() -> Option<&'static [&'static str]> {
+        Some(&["off", "2x", "4x"])
+    }
+
+    fn to_index(self) -> usize {
+        match self {
+            Oversampling::Off => 0,
+            Oversampling::X2 => 1,
+            Oversampling::X4 => 2,
+        }
+    }
+
+    fn from_index(index: usize) -> Self {
+        match index {
+            0 => Oversampling::Off,
+            1 => Oversampling::X2,
+            2 => Oversampling::X4,
+            _ => Oversampling::Off,
+        }
+    }
+}
+
+impl Oversampling {
+    pub fn get_factor(&self) -> usize {
+        match self {
+            Oversampling::Off => 1,
+            Oversampling::X2 => 2,
+            Oversampling::X4 => 4,
+        }
+    }
+}
+
+// nonlinear stage for the feedback loop. small signals pass with unity gain, louder ones get
+// squashed, so the loop can't run away even with the feedback above 100%.
+#[derive(Clone, Copy)]
+pub struct Saturator {
+    curve: SaturationCurve,
+    drive: f32,
+    mix: f32,
+    oversampling: Oversampling,
+    // anti-imaging filters after zero stuffing and anti-aliasing filters before decimation
+    upsampling_filters: [BiquadFilter; 2],
+    downsampling_filters: [BiquadFilter; 2],
+    sample_rate: f32,
+}
+
+impl Saturator {
+    pub fn new() -> Self {
+        Saturator {
+            curve: SaturationCurve::SoftClip,
+            drive: 1.0,
+            mix: 0.0,
+            oversampling: Oversampling::Off,
+            upsampling_filters: [BiquadFilter::new(); 2],
+            downsampling_filters: [BiquadFilter::new(); 2],
+            sample_rate: 44100.0,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.update_filters();
+    }
+
+    pub fn set_params(&mut self, curve: SaturationCurve, drive_db: f32, mix: f32, oversampling: Oversampling) {
+        self.curve = curve;
+        self.drive = 10.0_f32.powf(drive_db / 20.0);
+        self.mix = mix;
+        if oversampling != self.oversampling {
+            self.oversampling = oversampling;
+            self.update_filters();
+            self.reset();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for filter in self.upsampling_filters.iter_mut().chain(self.downsampling_filters.iter_mut()) {
+            filter.reset_filter();
+        }
+    }
+
+    fn update_filters(&mut self) {
+        // everything above the original nyquist frequency gets removed at the oversampled rate
+        let oversampled_rate = self.sample_rate * self.oversampling.get_factor() as f32;
+        let cutoff = 0.45 * self.sample_rate;
+        for (index, filter) in self.upsampling_filters.iter_mut().chain(self.downsampling_filters.iter_mut()).enumerate() {
+            filter.set_sample_rate(oversampled_rate);
+            filter.second_order_lpf_coefficients(oversampled_rate, cutoff, BUTTERWORTH_Q[index % 2]);
+        }
+    }
+
+    // the curve with the drive applied and undone again, so only the clipping level changes
+    fn shape(&self, x: f32) -> f32 {
+        self.curve.apply(x * self.drive) / self.drive
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        if self.mix <= 0.0 {
+            return x;
+        }
+
+        let factor = self.oversampling.get_factor();
+        let saturated = if factor == 1 {
+            self.shape(x)
+        } else {
+            // zero stuffing, the lost energy is made up for by scaling the one real sample
+            let mut y = 0.0;
+            for i in 0..factor {
+                let mut u = if i == 0 { x * factor as f32 } else { 0.0 };
+                for filter in self.upsampling_filters.iter_mut() {
+                    u = filter.process_left(u);
+                }
+                u = self.shape(u);
+                for filter in self.downsampling_filters.iter_mut() {
+                    u = filter.process_left(u);
+                }
+                // only the last sample of every group is kept
+                y = u;
+            }
+            y
+        };
+
+        (1.0 - self.mix) * x + self.mix * saturated
+    }
+}
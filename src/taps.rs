@@ -0,0 +1,139 @@
+use std::f32::consts::PI;
+
+use nih_plug::prelude::*;
+
+use crate::delay::MAX_DELAY_MS;
+use crate::delay_enums::{DelayMode, DelayTime, DelayTiming};
+use crate::filter::FilterType;
+
+pub const MAX_TAPS: usize = 8;
+
+// the default pattern walks up the grid, so enabling more taps stretches it out
+const DEFAULT_TIMES: [(DelayTime, DelayTiming); MAX_TAPS] = [
+    (DelayTime::_1_8, DelayTiming::Straight),
+    (DelayTime::_1_4, DelayTiming::Straight),
+    (DelayTime::_1_4, DelayTiming::Dotted),
+    (DelayTime::_1_2, DelayTiming::Straight),
+    (DelayTime::_1_2, DelayTiming::Dotted),
+    (DelayTime::_1, DelayTiming::Straight),
+    (DelayTime::_1, DelayTiming::Dotted),
+    (DelayTime::_2, DelayTiming::Straight),
+];
+
+#[derive(Params)]
+pub struct TapParams {
+    #[id = "tap-mode"]
+    pub delay_mode: EnumParam<DelayMode>,
+
+    #[id = "tap-time"]
+    pub delay_time: EnumParam<DelayTime>,
+
+    #[id = "tap-timing"]
+    pub delay_timing: EnumParam<DelayTiming>,
+
+    #[id = "tap-ms"]
+    pub delay_ms: FloatParam,
+
+    #[id = "tap-gain"]
+    pub gain: FloatParam,
+
+    #[id = "tap-pan"]
+    pub pan: FloatParam,
+
+    #[id = "tap-mute"]
+    pub mute: BoolParam,
+
+    #[id = "tap-feedback"]
+    pub feedback_source: BoolParam,
+
+    #[id = "tap-cutoff"]
+    pub cutoff: FloatParam,
+
+    #[id = "tap-resonance"]
+    pub resonance: FloatParam,
+
+    #[id = "tap-filter-type"]
+    pub filter_type: EnumParam<FilterType>,
+}
+
+impl TapParams {
+    pub fn new(index: usize) -> Self {
+        let (delay_time, delay_timing) = DEFAULT_TIMES[index];
+        let name = format!("Tap {}", index + 1);
+
+        Self {
+            delay_mode: EnumParam::new(format!("{name} Mode"), DelayMode::Sync),
+
+            delay_time: EnumParam::new(format!("{name} Time"), delay_time),
+
+            delay_timing: EnumParam::new(format!("{name} Timing"), delay_timing),
+
+            delay_ms: FloatParam::new(format!("{name} Time (ms)"), 125.0 * (index + 1) as f32, FloatRange::Skewed { min: 1.0, max: MAX_DELAY_MS, factor: 0.3 })
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            gain: FloatParam::new(format!("{name} Level"), 1.0 - 0.1 * index as f32, FloatRange::Linear { min: 0.0, max: 1.0 })
+            .with_smoother(SmoothingStyle::Linear(20.0))
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            // alternate the taps between the sides
+            pan: FloatParam::new(format!("{name} Pan"), if index % 2 == 0 { -0.5 } else { 0.5 }, FloatRange::Linear { min: -1.0, max: 1.0 })
+            .with_smoother(SmoothingStyle::Linear(20.0))
+            .with_value_to_string(formatters::v2s_f32_panning())
+            .with_string_to_value(formatters::s2v_f32_panning()),
+
+            mute: BoolParam::new(format!("{name} Mute"), false),
+
+            // the fourth tap closes the default four tap pattern, so that's the one repeating
+            feedback_source: BoolParam::new(format!("{name} Feedback Source"), index == 3),
+
+            cutoff: FloatParam::new(format!("{name} Cutoff"), 20000.0, FloatRange::Skewed { min: 20.0, max: 20000.0, factor: 0.2 })
+            .with_smoother(SmoothingStyle::Logarithmic(20.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(2))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+
+            resonance: FloatParam::new(format!("{name} Resonance"), 0.707, FloatRange::Linear { min: 0.5, max: 2.0 })
+            .with_smoother(SmoothingStyle::Linear(20.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            filter_type: EnumParam::new(format!("{name} Filter Type"), FilterType::LowPass2),
+        }
+    }
+
+    // advances the smoothers by one sample. has to be called once per sample for every tap, even
+    // the inactive ones, so a tap that gets enabled doesn't ramp from an old value.
+    pub fn next_smoothed(&self) -> SmoothedTap {
+        SmoothedTap {
+            delay_ms: self.delay_ms.smoothed.next(),
+            gain: self.gain.smoothed.next(),
+            pan: self.pan.smoothed.next(),
+            cutoff: self.cutoff.smoothed.next(),
+            resonance: self.resonance.smoothed.next(),
+        }
+    }
+}
+
+// the continuous tap settings for the current sample
+#[derive(Clone, Copy, Default)]
+pub struct SmoothedTap {
+    pub delay_ms: f32,
+    pub gain: f32,
+    pub pan: f32,
+    pub cutoff: f32,
+    pub resonance: f32,
+}
+
+// constant power panning across the front pair. a single channel isn't panned at all, and the
+// channels after the front pair (center, LFE and surrounds) get every tap at full level.
+pub fn pan_gain(pan: f32, channel: usize, num_channels: usize) -> f32 {
+    if num_channels < 2 || channel >= 2 {
+        return 1.0;
+    }
+    let angle = (pan + 1.0) * PI / 4.0;
+    if channel == 0 {
+        angle.cos()
+    } else {
+        angle.sin()
+    }
+}